@@ -0,0 +1,18 @@
+use std::path::Path;
+
+// The drug model is shared with the client so the database can be decoded at runtime.
+#[allow(dead_code)]
+#[path = "src/drugs_parser/model.rs"]
+mod model;
+
+fn main() {
+    println!("cargo:rerun-if-changed=../drugs.json");
+    println!("cargo:rerun-if-changed=src/drugs_parser/model.rs");
+
+    let json = std::fs::read_to_string("../drugs.json").expect("Could not read drugs.json");
+    let db: model::DrugDatabase = serde_json::from_str(&json).expect("Could not parse drugs.json");
+    let bin = bincode::serialize(&db).expect("Could not serialize drug database");
+
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    std::fs::write(Path::new(&out_dir).join("drugs.bin"), bin).expect("Could not write drugs.bin");
+}
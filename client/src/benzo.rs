@@ -0,0 +1,206 @@
+use chrono::{Duration, Utc};
+use std::collections::{BTreeMap, HashMap};
+use uuid::Uuid;
+
+use crate::drugs_parser::{parse_diazepam_equivalence, DRUGS};
+use crate::ingestions::Ingestion;
+use crate::ingestions_util::{ensure_ingestion_files, format_dose, ingestion_datetime};
+use crate::util::wrap_text;
+
+/// Window `list-ingestions` totals benzodiazepines over, in hours.
+pub const DEFAULT_WINDOW_HOURS: i64 = 24;
+
+/// Diazepam equivalence of the ingested substance as a range of ratios, `None` for other
+/// substances.
+fn equivalence(ingestion: &Ingestion) -> Option<(f64, f64)> {
+    let drug = DRUGS.get(&ingestion.substance.name)?;
+    parse_diazepam_equivalence(drug.properties.dose_to_diazepam.as_ref()?)
+}
+
+/// Diazepam-equivalent milligrams of a benzodiazepine ingestion as a range, `None` for other
+/// substances or doses that can't be converted to milligrams.
+pub fn diazepam_equivalent(ingestion: &Ingestion) -> Option<(f64, f64)> {
+    let (min, max) = equivalence(ingestion)?;
+    let milligrams = ingestion.dose.milligrams(&ingestion.substance)?;
+    Some((milligrams * min, milligrams * max))
+}
+
+/// Benzodiazepine ingestions of a time window, oldest first.
+struct RecentBenzos<'a> {
+    converted: Vec<(&'a Ingestion, (f64, f64))>,
+    /// Doses that can't be converted to milligrams and are missing from every total.
+    skipped: Vec<&'a Ingestion>,
+}
+
+/// Benzodiazepine ingestions of the last `hours` hours.
+fn recent_benzos(ingestions: &HashMap<Uuid, Ingestion>, hours: i64) -> RecentBenzos<'_> {
+    let now = Utc::now().naive_utc();
+    let start = now - Duration::hours(hours);
+    let mut benzos = ingestions
+        .values()
+        .filter(|ingestion| {
+            let taken = ingestion_datetime(ingestion);
+            start <= taken && taken <= now && equivalence(ingestion).is_some()
+        })
+        .collect::<Vec<_>>();
+    benzos.sort_by_key(|ingestion| ingestion_datetime(ingestion));
+
+    let mut recent = RecentBenzos {
+        converted: Vec::new(),
+        skipped: Vec::new(),
+    };
+    for ingestion in benzos {
+        match diazepam_equivalent(ingestion) {
+            Some(equivalent) => recent.converted.push((ingestion, equivalent)),
+            None => recent.skipped.push(ingestion),
+        }
+    }
+    recent
+}
+
+fn sum(equivalents: impl Iterator<Item = (f64, f64)>) -> (f64, f64) {
+    equivalents.fold((0.0, 0.0), |(min, max), (a, b)| (min + a, max + b))
+}
+
+pub fn format_equivalent((min, max): (f64, f64)) -> String {
+    let round = |mg: f64| (mg * 10.0).round() / 10.0;
+    if round(min) == round(max) {
+        format!("~{}mg diazepam", round(min))
+    } else {
+        format!("~{}-{}mg diazepam", round(min), round(max))
+    }
+}
+
+/// Marks `total` as partial when `skipped` doses are missing from it.
+fn partial(total: String, skipped: usize) -> String {
+    match skipped {
+        0 => total,
+        1 => format!("{} (partial, 1 dose can't be converted)", total),
+        n => format!("{} (partial, {} doses can't be converted)", total, n),
+    }
+}
+
+/// One line total for `list-ingestions`, `None` without benzodiazepines in the window.
+pub fn summary(ingestions: &HashMap<Uuid, Ingestion>, hours: i64) -> Option<String> {
+    let benzos = recent_benzos(ingestions, hours);
+    if benzos.converted.is_empty() && benzos.skipped.is_empty() {
+        return None;
+    }
+    let total = format_equivalent(sum(benzos.converted.iter().map(|(_, eq)| *eq)));
+    Some(format!(
+        "Benzodiazepines in the last {} hours: {}",
+        hours,
+        partial(total, benzos.skipped.len())
+    ))
+}
+
+/// Shows benzodiazepine ingestions of the last `hours` hours in diazepam-equivalent milligrams.
+pub fn benzo_equiv(hours: i64) -> Result<(), std::io::Error> {
+    let ingestions = ensure_ingestion_files();
+    let benzos = recent_benzos(&ingestions, hours);
+    if benzos.converted.is_empty() && benzos.skipped.is_empty() {
+        println!("No benzodiazepines logged in the last {} hours.", hours);
+        return Ok(());
+    }
+
+    for ingestion in &benzos.skipped {
+        println!(
+            "Skipping {} of {} taken at {}, it can't be converted to mg",
+            ingestion.dose,
+            ingestion.substance.name,
+            ingestion_datetime(ingestion).format("%Y-%m-%d %H:%M")
+        );
+    }
+    if !benzos.skipped.is_empty() {
+        println!();
+    }
+
+    let mut totals: BTreeMap<&str, Vec<(f64, f64)>> = BTreeMap::new();
+    for (ingestion, equivalent) in &benzos.converted {
+        println!(
+            "{}  {:<16}{:<20}{}",
+            ingestion_datetime(ingestion).format("%Y-%m-%d %H:%M"),
+            ingestion.substance.name,
+            format_dose(ingestion),
+            format_equivalent(*equivalent)
+        );
+        totals
+            .entry(ingestion.substance.name.as_str())
+            .or_default()
+            .push(*equivalent);
+    }
+
+    println!("\nTotals over the last {} hours", hours);
+    for (name, equivalents) in &totals {
+        let skipped = benzos
+            .skipped
+            .iter()
+            .filter(|ingestion| ingestion.substance.name == *name)
+            .count();
+        println!(
+            "  {:<20}{}",
+            name,
+            partial(format_equivalent(sum(equivalents.iter().copied())), skipped)
+        );
+    }
+    println!(
+        "  {:<20}{}",
+        "All",
+        partial(
+            format_equivalent(sum(benzos.converted.iter().map(|(_, eq)| *eq))),
+            benzos.skipped.len()
+        )
+    );
+    let note = "Equivalences are rough and vary between people, they are not a dosing guide. \
+                Combining benzodiazepines adds up, the total above is what your body is dealing \
+                with.";
+    println!("\n{}", wrap_text(note, 80, ""));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::summary;
+    use crate::ingestions::{Dose, Ingestion, IngestionMethod};
+    use crate::substances::{Substance, SubstanceClass};
+    use crate::units::DoseUnit;
+    use chrono::Utc;
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    fn alprazolam(unit: DoseUnit, value: f64) -> (Uuid, Ingestion) {
+        let now = Utc::now().naive_utc();
+        let ingestion = Ingestion {
+            substance: Substance {
+                name: "Alprazolam".to_string(),
+                substance_class: SubstanceClass::Depressant,
+                concentration: None,
+                units: vec![],
+            },
+            dose: Dose { unit, value },
+            ingestion_method: IngestionMethod::Oral,
+            time: now.time(),
+            date: now.date(),
+        };
+        (Uuid::new_v4(), ingestion)
+    }
+
+    #[test]
+    fn marks_totals_with_unconvertible_doses_as_partial() {
+        let mut ingestions = [alprazolam(DoseUnit::Mg, 1.0)]
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        let total = summary(&ingestions, 24).unwrap();
+        assert!(!total.contains("partial"), "{}", total);
+
+        // No concentration to convert millilitres with
+        ingestions.extend([alprazolam(DoseUnit::Ml, 1.0)]);
+        let partial = summary(&ingestions, 24).unwrap();
+        assert!(
+            partial.ends_with("(partial, 1 dose can't be converted)"),
+            "{}",
+            partial
+        );
+        assert!(partial.starts_with(&total), "{}", partial);
+    }
+}
@@ -0,0 +1,247 @@
+use super::{Category, ComboGroup, Drug};
+
+/// Drugs that belong to a combo group but can't be matched by name or category.
+const MEMBERS: [(&str, &[ComboGroup]); 17] = [
+    ("2-fa", &[ComboGroup::Amphetamines]),
+    ("2-fea", &[ComboGroup::Amphetamines]),
+    ("2-fma", &[ComboGroup::Amphetamines]),
+    ("3-fma", &[ComboGroup::Amphetamines]),
+    ("4-fa", &[ComboGroup::Amphetamines]),
+    ("4-fea", &[ComboGroup::Amphetamines]),
+    ("4-fma", &[ComboGroup::Amphetamines]),
+    ("adderall", &[ComboGroup::Amphetamines]),
+    ("dexedrine", &[ComboGroup::Amphetamines]),
+    ("vyvanse", &[ComboGroup::Amphetamines]),
+    ("moclobemide", &[ComboGroup::Maois]),
+    ("ayahuasca", &[ComboGroup::Maois, ComboGroup::Dmt]),
+    ("changa", &[ComboGroup::Maois, ComboGroup::Dmt]),
+    ("1,4-butanediol", &[ComboGroup::GhbGbl]),
+    ("psilocin", &[ComboGroup::Mushrooms]),
+    ("truffles", &[ComboGroup::Mushrooms]),
+    ("peyote", &[ComboGroup::Mescaline]),
+];
+
+impl Drug {
+    /// Combo groups the drug belongs to, e.g. `benzodiazepines` for alprazolam.
+    ///
+    /// Groups are resolved from the drug's name and aliases, its categories, name patterns of
+    /// chemical families like the 2C-x and a list of known members.
+    pub fn combo_groups(&self) -> Vec<ComboGroup> {
+        let names = self.lowercase_names();
+        let categories = self.categories.as_deref().unwrap_or_default();
+
+        let mut groups = self.named_groups();
+        for (category, group) in [
+            (Category::Benzodiazepine, ComboGroup::Benzodiazepines),
+            (Category::Opioid, ComboGroup::Opioids),
+            (Category::Ssri, ComboGroup::Ssris),
+        ] {
+            if categories.contains(&category) {
+                groups.push(group);
+            }
+        }
+        for name in &names {
+            groups.extend(name_pattern_groups(name));
+        }
+        // Only the name itself, aliases like `hexahdromethamphetamine` for propylhexedrine or
+        // `4-methylthioamphetamine` belong to drugs with their own interactions
+        if names[0].contains("amphetamine") || names[0].contains("amfetamine") {
+            groups.push(ComboGroup::Amphetamines);
+        }
+        for (member, member_groups) in MEMBERS {
+            if self.name == member {
+                groups.extend(member_groups.iter().cloned());
+            }
+        }
+
+        // Keep the order of `ComboGroup::KNOWN` and drop duplicates
+        ComboGroup::KNOWN
+            .into_iter()
+            .filter(|group| groups.contains(group))
+            .collect()
+    }
+
+    /// Combo groups named after the drug itself or one of its aliases, e.g. `tramadol` but not
+    /// `opioids` for tramadol. Only these share the drug's own interactions.
+    pub fn named_groups(&self) -> Vec<ComboGroup> {
+        let names = self.lowercase_names();
+        ComboGroup::KNOWN
+            .into_iter()
+            .filter(|group| {
+                group
+                    .as_str()
+                    .split('/')
+                    .any(|part| names.iter().any(|name| name == part))
+            })
+            .collect()
+    }
+
+    /// The drug's combo groups with the named ones first, the order to look interactions up in
+    /// since a drug's own table is more specific than the one of its class.
+    pub fn lookup_groups(&self) -> Vec<ComboGroup> {
+        let named = self.named_groups();
+        let rest = self
+            .combo_groups()
+            .into_iter()
+            .filter(|group| !named.contains(group));
+        named.iter().cloned().chain(rest).collect()
+    }
+
+    fn lowercase_names(&self) -> Vec<String> {
+        std::iter::once(&self.name)
+            .chain(self.aliases.iter().flatten())
+            .map(|name| name.to_lowercase())
+            .collect()
+    }
+}
+
+fn name_pattern_groups(name: &str) -> Vec<ComboGroup> {
+    let mut groups = Vec::new();
+    if name.contains("nbome") || (name.starts_with("25") && name.contains("-nb")) {
+        groups.push(ComboGroup::Nbomes);
+    } else if name.starts_with("2c-t") {
+        groups.push(ComboGroup::C2TX);
+    } else if name.starts_with("2c-") {
+        groups.push(ComboGroup::C2X);
+    }
+    if name.starts_with("5-meo-") {
+        groups.push(ComboGroup::C5MeoXxt);
+    }
+    // DOB, DOC, DOM, DOET, ...
+    if name.starts_with("do")
+        && (3..=4).contains(&name.len())
+        && name.chars().all(|c| c.is_ascii_lowercase())
+    {
+        groups.push(ComboGroup::Dox);
+    }
+    // Lysergamides like 1P-LSD, ALD-52 and ETH-LAD
+    if name.contains("lsd") || name.ends_with("-lad") || name == "ald-52" {
+        groups.push(ComboGroup::Lsd);
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::drugs_parser::{ComboGroup, Status, DRUGS};
+
+    fn groups(name: &str) -> Vec<String> {
+        DRUGS
+            .get(name)
+            .unwrap_or_else(|| panic!("{} is not in the drug database", name))
+            .combo_groups()
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn resolves_groups_from_categories() {
+        assert_eq!(groups("alprazolam"), vec!["benzodiazepines"]);
+        assert_eq!(groups("clonazepam"), vec!["benzodiazepines"]);
+        assert_eq!(groups("heroin"), vec!["opioids"]);
+        assert_eq!(groups("sertraline"), vec!["ssris"]);
+        assert_eq!(groups("tramadol"), vec!["opioids", "tramadol"]);
+    }
+
+    #[test]
+    fn resolves_groups_from_name_patterns() {
+        assert_eq!(groups("2c-b"), vec!["2c-x"]);
+        assert_eq!(groups("2c-t-7"), vec!["2c-t-x"]);
+        assert_eq!(groups("25i-nbome"), vec!["nbomes"]);
+        assert_eq!(groups("2c-b-fly-nbome"), vec!["nbomes"]);
+        assert_eq!(groups("5-meo-dmt"), vec!["5-meo-xxt"]);
+        assert_eq!(groups("doc"), vec!["dox"]);
+        assert_eq!(groups("methamphetamine"), vec!["amphetamines"]);
+        assert_eq!(groups("adderall"), vec!["amphetamines"]);
+        assert_eq!(groups("4-fa"), vec!["amphetamines"]);
+        // Aliases mentioning amphetamine don't make a drug one
+        assert!(groups("propylhexedrine").is_empty());
+        assert!(groups("4-mta").is_empty());
+        assert_eq!(groups("1p-lsd"), vec!["lsd"]);
+    }
+
+    #[test]
+    fn resolves_groups_from_names_and_aliases() {
+        assert_eq!(groups("xanax"), vec!["benzodiazepines"]);
+        assert_eq!(groups("ethanol"), vec!["alcohol"]);
+        assert_eq!(groups("ghb"), vec!["ghb/gbl"]);
+        assert_eq!(groups("1,4-butanediol"), vec!["ghb/gbl"]);
+        assert_eq!(groups("ayahuasca"), vec!["dmt", "maois"]);
+        assert_eq!(groups("psilocin"), vec!["mushrooms"]);
+    }
+
+    #[test]
+    fn every_combo_group_has_members() {
+        for group in ComboGroup::KNOWN {
+            assert!(
+                DRUGS.drugs().any(|d| d.combo_groups().contains(&group)),
+                "no drug belongs to {}",
+                group
+            );
+        }
+    }
+
+    #[test]
+    fn every_combo_table_belongs_to_a_group() {
+        for drug in DRUGS.drugs().filter(|d| d.combos.is_some()) {
+            assert!(
+                !drug.combo_groups().is_empty(),
+                "{} has combos but no group",
+                drug.name
+            );
+        }
+    }
+
+    #[test]
+    fn finds_combos_for_specific_compounds() {
+        let alprazolam = DRUGS.get("alprazolam").unwrap();
+        let alcohol = DRUGS.get("alcohol").unwrap();
+        let combo = DRUGS.combo(alprazolam, alcohol).unwrap();
+        assert!(matches!(combo.status, Status::Dangerous));
+
+        let heroin = DRUGS.get("heroin").unwrap();
+        let tramadol = DRUGS.get("tramadol").unwrap();
+        assert!(DRUGS.combo(heroin, tramadol).is_some());
+
+        let mdma = DRUGS.get("mdma").unwrap();
+        let combo = DRUGS.combo(heroin, mdma).unwrap();
+        assert!(matches!(combo.status, Status::LowRiskAndNoSynergy));
+        let combo = DRUGS.combo(tramadol, mdma).unwrap();
+        assert!(matches!(combo.status, Status::Dangerous));
+        let combo = DRUGS
+            .group_combo(&[ComboGroup::Opioids], &[ComboGroup::Lsd])
+            .unwrap();
+        assert!(matches!(combo.status, Status::LowRiskAndNoSynergy));
+    }
+
+    #[test]
+    fn finds_the_same_combo_both_ways() {
+        let status = |a: &[ComboGroup], b: &[ComboGroup]| {
+            DRUGS
+                .group_combo(a, b)
+                .map(|combo| combo.status.to_string())
+        };
+        for a in ComboGroup::KNOWN {
+            for b in ComboGroup::KNOWN {
+                assert_eq!(
+                    status(std::slice::from_ref(&a), std::slice::from_ref(&b)),
+                    status(std::slice::from_ref(&b), std::slice::from_ref(&a)),
+                    "{} + {}",
+                    a,
+                    b
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn uses_own_tables_only_for_named_groups() {
+        let tramadol = DRUGS.get("tramadol").unwrap();
+        assert_eq!(tramadol.named_groups(), vec![ComboGroup::Tramadol]);
+        assert_eq!(
+            tramadol.lookup_groups(),
+            vec![ComboGroup::Tramadol, ComboGroup::Opioids]
+        );
+    }
+}
@@ -1,271 +1,368 @@
-use serde::{Deserialize, Serialize};
+use lazy_static::lazy_static;
 use std::collections::HashMap;
+
+use crate::substances::SubstanceClass;
+mod groups;
+mod model;
 mod parser;
 
-pub fn parse() {
-    let file = include_str!("../../../drugs.json");
-    let db: DrugDatabase = serde_json::from_str(file).unwrap();
-    println!("{:?}", db);
-}
+pub use model::*;
+pub use parser::{
+    parse_diazepam_equivalence, parse_dose_range, parse_half_life, parse_timing,
+    parse_tolerance_days, DoseRange, RangeUnit, TimeRange,
+};
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct DrugDatabase(HashMap<String, Drug>);
-
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Drug {
-    pub aliases: Option<Vec<String>>,
-    pub categories: Option<Vec<Category>>,
-    #[serde(rename = "formatted_aftereffects")]
-    pub formatted_aftereffects: Option<Duration>,
-    #[serde(rename = "formatted_dose")]
-    pub formatted_dose: Option<Dose>,
-    #[serde(rename = "formatted_duration")]
-    pub formatted_duration: Option<Duration>,
-    #[serde(rename = "formatted_effects")]
-    pub formatted_effects: Option<Vec<String>>,
-    #[serde(rename = "formatted_onset")]
-    pub formatted_onset: Option<Duration>,
-    pub links: Option<Links>,
-    pub name: String,
-    #[serde(rename = "pretty_name")]
-    pub pretty_name: String,
-    pub properties: Properties,
-    pub pweffects: Option<HashMap<String, String>>,
-    #[serde(rename = "dose_note")]
-    pub dose_note: Option<String>,
-    pub sources: Option<Sources>,
-    pub combos: Option<Combos>,
+lazy_static! {
+    /// The bundled drug database, decoded on first use.
+    pub static ref DRUGS: DrugIndex = DrugIndex::load();
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "kebab-case")]
-pub enum Category {
-    Depressant,
-    HabitForming,
-    Tentative,
-    ResearchChemical,
-    Psychedelic,
-    Stimulant,
-    Dissociative,
-    Inactive,
-    Empathogen,
-    Common,
-    Benzodiazepine,
-    Opioid,
-    Supplement,
-    Nootropic,
-    Barbiturate,
-    Deliriant,
-    Ssri,
+/// `drugs.json` serialized with bincode by `build.rs`.
+static DRUGS_BIN: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/drugs.bin"));
+
+/// Read-only view of the drug database that resolves names and aliases.
+pub struct DrugIndex {
+    database: DrugDatabase,
+    lookup: HashMap<String, String>,
+    /// Combo group to the drug that carries the interaction table for it
+    combo_tables: HashMap<ComboGroup, String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Dose {
-    pub oral: Option<Dosage>,
-    pub insufflated: Option<Dosage>,
-    pub rectal: Option<Dosage>,
-    pub vapourized: Option<Dosage>,
-    pub intravenous: Option<Dosage>,
-    pub smoked: Option<Dosage>,
-    pub sublingual: Option<Dosage>,
-    pub buccal: Option<Dosage>,
-    pub intramuscular: Option<Dosage>,
-    pub transdermal: Option<Dosage>,
-    pub hbwr: Option<Dosage>,
-    #[serde(rename = "Morning_Glory")]
-    pub morning_glory: Option<Dosage>,
-    pub dried: Option<Dosage>,
-    pub fresh: Option<Dosage>,
-    #[serde(rename = "Insufflated(Pure)")]
-    pub insufflated_pure: Option<Dosage>,
-    #[serde(rename = "Oral(Benzedrex)")]
-    pub oral_benzedrex: Option<Dosage>,
-    #[serde(rename = "Oral(Pure)")]
-    pub oral_pure: Option<Dosage>,
-    pub dry: Option<Dosage>,
-    pub wet: Option<Dosage>,
+impl DrugIndex {
+    fn load() -> DrugIndex {
+        let database: DrugDatabase =
+            bincode::deserialize(DRUGS_BIN).expect("Could not decode the bundled drug database");
+
+        let mut lookup = HashMap::new();
+        for (key, drug) in database.0.iter() {
+            let aliases = drug.aliases.iter().flatten();
+            for alias in aliases {
+                lookup
+                    .entry(alias.to_lowercase())
+                    .or_insert_with(|| key.clone());
+            }
+        }
+        // Real names always win over aliases of other drugs
+        for (key, drug) in database.0.iter() {
+            lookup.insert(drug.pretty_name.to_lowercase(), key.clone());
+            lookup.insert(key.to_lowercase(), key.clone());
+        }
+
+        let mut combo_tables = HashMap::new();
+        for (key, drug) in database.0.iter().filter(|(_, d)| d.combos.is_some()) {
+            // Class groups like `opioids` have a table of their own, which a member's table
+            // must not replace
+            for group in drug.named_groups() {
+                combo_tables.insert(group, key.clone());
+            }
+        }
+
+        DrugIndex {
+            database,
+            lookup,
+            combo_tables,
+        }
+    }
+
+    /// Looks up a drug by its name, pretty name or one of its aliases, ignoring case.
+    pub fn get(&self, name: &str) -> Option<&Drug> {
+        self.lookup
+            .get(&name.trim().to_lowercase())
+            .and_then(|key| self.database.0.get(key))
+    }
+
+    pub fn drugs(&self) -> impl Iterator<Item = &Drug> {
+        self.database.0.values()
+    }
+
+    /// Finds the interaction between two drugs in either drug's combo table.
+    pub fn combo(&self, a: &Drug, b: &Drug) -> Option<&Combo> {
+        self.group_combo(&a.lookup_groups(), &b.lookup_groups())
+    }
+
+    /// Finds the interaction between any of the combo groups in `groups_a` and `groups_b`.
+    pub fn group_combo(&self, groups_a: &[ComboGroup], groups_b: &[ComboGroup]) -> Option<&Combo> {
+        for group_a in groups_a {
+            for group_b in groups_b {
+                for (table, other) in [(group_a, group_b), (group_b, group_a)] {
+                    let combo = self
+                        .combo_tables
+                        .get(table)
+                        .and_then(|key| self.database.0.get(key))
+                        .and_then(|drug| drug.combos.as_ref())
+                        .and_then(|combos| combos.0.get(other));
+                    if combo.is_some() {
+                        return combo;
+                    }
+                }
+            }
+        }
+        None
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Dosage {
-    pub common: Option<String>,
-    pub light: Option<String>,
-    pub strong: Option<String>,
-    pub threshold: Option<String>,
-    pub heavy: Option<String>,
-    pub dangerous: Option<String>,
-    pub fatal: Option<String>,
-    pub note: Option<String>,
+impl Drug {
+    /// Picks the `SubstanceClass` that best describes the drug based on its categories.
+    pub fn substance_class(&self) -> Option<SubstanceClass> {
+        if self.name == "cannabis" {
+            return Some(SubstanceClass::Cannabinoid);
+        }
+        let categories = self.categories.as_deref().unwrap_or_default();
+        let has = |category: Category| categories.contains(&category);
+        if has(Category::Inactive) {
+            None
+        } else if has(Category::Empathogen) {
+            Some(SubstanceClass::Empathogen)
+        } else if has(Category::Dissociative) {
+            Some(SubstanceClass::Dissociative)
+        } else if has(Category::Deliriant) {
+            Some(SubstanceClass::Deliriant)
+        } else if has(Category::Psychedelic) {
+            Some(SubstanceClass::Psychedelic)
+        } else if has(Category::Stimulant) {
+            Some(SubstanceClass::Stimulant)
+        } else if has(Category::Depressant)
+            || has(Category::Benzodiazepine)
+            || has(Category::Opioid)
+            || has(Category::Barbiturate)
+        {
+            Some(SubstanceClass::Depressant)
+        } else if has(Category::Ssri) {
+            Some(SubstanceClass::Neurotransmitter)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_common(&self) -> bool {
+        self.categories
+            .as_deref()
+            .unwrap_or_default()
+            .contains(&Category::Common)
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Duration {
-    #[serde(rename = "_unit")]
-    pub unit: Option<Unit>,
-    pub value: Option<String>,
-    pub insufflated: Option<String>,
-    pub oral: Option<String>,
-    pub rectal: Option<String>,
-    pub vapourized: Option<String>,
-    pub smoked: Option<String>,
-    #[serde(rename = "Oral_ER")]
-    pub oral_er: Option<String>,
-    #[serde(rename = "Oral_IR")]
-    pub oral_ir: Option<String>,
-    pub intramuscular: Option<String>,
-    pub intravenous: Option<String>,
-    pub metabolites: Option<String>,
-    pub parent: Option<String>,
-    #[serde(rename = "Oral_MAOI")]
-    pub oral_maoi: Option<String>,
-    pub buccal: Option<String>,
-    pub transdermal: Option<String>,
-    pub sublingual: Option<String>,
-    #[serde(rename = "Insufflated_IR")]
-    pub insufflated_ir: Option<String>,
-    #[serde(rename = "Insufflated_XR")]
-    pub insufflated_xr: Option<String>,
+/// Reference dose tier, ordered from weakest to strongest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, strum::Display)]
+pub enum DoseTier {
+    #[strum(to_string = "Below threshold")]
+    BelowThreshold,
+    Threshold,
+    Light,
+    Common,
+    Strong,
+    Heavy,
+    Dangerous,
+    Fatal,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Links {
-    pub experiences: String,
-    pub pihkal: Option<String>,
-    pub tihkal: Option<String>,
+impl DoseTier {
+    /// Tiers that warrant a loud warning before an ingestion is logged.
+    pub fn is_excessive(&self) -> bool {
+        *self >= DoseTier::Heavy
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Properties {
-    #[serde(rename = "after-effects")]
-    pub after_effects: Option<String>,
-    pub aliases: Option<Vec<String>>,
-    pub avoid: Option<String>,
-    pub categories: Option<Vec<Category>>,
-    pub dose: Option<String>,
-    pub duration: Option<String>,
-    #[serde(rename = "half-life")]
-    pub half_life: Option<String>,
-    pub onset: Option<String>,
-    pub summary: Option<String>,
-    #[serde(rename = "test-kits")]
-    pub test_kits: Option<String>,
-    pub experiences: Option<String>,
-    pub warning: Option<String>,
-    pub marquis: Option<String>,
-    pub effects: Option<String>,
-    pub risks: Option<String>,
-    pub comeup: Option<String>,
-    pub note: Option<String>,
-    pub detection: Option<String>,
-    pub wiki: Option<String>,
-    pub mdma: Option<String>,
-    pub tolerance: Option<String>,
-    pub bioavailability: Option<String>,
-    #[serde(rename = "dose_to_diazepam")]
-    pub dose_to_diazepam: Option<String>,
-    #[serde(rename = "adverse-effects")]
-    pub adverse_effects: Option<String>,
-    pub chemistry: Option<String>,
-    pub contraindications: Option<String>,
-    pub legal: Option<String>,
-    #[serde(rename = "overdose-symptoms")]
-    pub overdose_symptoms: Option<String>,
-    pub pharmacokinetics: Option<String>,
-    pub pharmacology: Option<String>,
-    pub obtain: Option<String>,
-    pub pharmacodynamics: Option<String>,
-    #[serde(rename = "side-effects")]
-    pub side_effects: Option<String>,
-    pub molecule: Option<String>,
-    pub vaporization: Option<String>,
-    pub calculator: Option<String>,
-    pub chart: Option<String>,
-    pub oral: Option<String>,
-    #[serde(rename = "general-advice")]
-    pub general_advice: Option<String>,
-    pub potentiators: Option<String>,
+impl Dosage {
+    pub fn tiers(&self) -> [(DoseTier, Option<&String>); 7] {
+        [
+            (DoseTier::Threshold, self.threshold.as_ref()),
+            (DoseTier::Light, self.light.as_ref()),
+            (DoseTier::Common, self.common.as_ref()),
+            (DoseTier::Strong, self.strong.as_ref()),
+            (DoseTier::Heavy, self.heavy.as_ref()),
+            (DoseTier::Dangerous, self.dangerous.as_ref()),
+            (DoseTier::Fatal, self.fatal.as_ref()),
+        ]
+    }
+
+    /// Parsed ranges of every tier. Values without a unit, e.g. `50-75`, borrow the unit of the
+    /// other tiers.
+    pub fn ranges(&self) -> Vec<(DoseTier, DoseRange)> {
+        let tiers = self.tiers();
+        let parsed = tiers
+            .iter()
+            .map(|(tier, value)| (*tier, value.and_then(|v| parse_dose_range(v))))
+            .collect::<Vec<_>>();
+        let unit = parsed.iter().find_map(|(_, range)| range.map(|r| r.unit));
+        tiers
+            .iter()
+            .zip(parsed)
+            .filter_map(|((_, value), (tier, range))| {
+                let range = range.or_else(|| {
+                    let unit = unit?;
+                    let value = (*value)?.trim().trim_end_matches('.');
+                    let (amount, plus) = match value.strip_suffix('+') {
+                        Some(amount) => (amount, "+"),
+                        None => (value, ""),
+                    };
+                    parse_dose_range(&format!("{}{}{}", amount, unit, plus))
+                })?;
+                Some((tier, range))
+            })
+            .collect()
+    }
+
+    /// Classifies `amount` of `unit` as the strongest tier whose range it reaches.
+    ///
+    /// Returns `None` if the dose can't be compared to the reference ranges, e.g. because they
+    /// are given in a different kind of unit.
+    pub fn classify(&self, amount: f64, unit: RangeUnit) -> Option<DoseTier> {
+        let ranges = self.ranges();
+        if ranges.is_empty() {
+            return None;
+        }
+        let mut classified = DoseTier::BelowThreshold;
+        for (tier, range) in ranges {
+            let amount = unit.convert(amount, range.unit)?;
+            if amount >= range.min {
+                classified = tier;
+            }
+        }
+        Some(classified)
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Combos {
-    #[serde(rename = "2c-t-x")]
-    pub c2_t_x: Option<Combo>,
-    #[serde(rename = "2c-x")]
-    pub c2_x: Option<Combo>,
-    #[serde(rename = "5-meo-xxt")]
-    pub c5_meo_xxt: Option<Combo>,
-    pub alcohol: Option<Combo>,
-    pub amphetamines: Option<Combo>,
-    pub amt: Option<Combo>,
-    pub benzodiazepines: Option<Combo>,
-    pub caffeine: Option<Combo>,
-    pub cannabis: Option<Combo>,
-    pub cocaine: Option<Combo>,
-    pub dextromethorphan: Option<Combo>,
-    pub diphenhydramine: Option<Combo>,
-    pub dmt: Option<Combo>,
-    pub dox: Option<Combo>,
-    #[serde(rename = "ghb/gbl")]
-    pub ghb_gbl: Option<Combo>,
-    pub lithium: Option<Combo>,
-    pub ketamine: Option<Combo>,
-    pub lsd: Option<Combo>,
-    pub maois: Option<Combo>,
-    pub mdma: Option<Combo>,
-    pub mephedrone: Option<Combo>,
-    pub mescaline: Option<Combo>,
-    pub mushrooms: Option<Combo>,
-    pub mxe: Option<Combo>,
-    pub nbomes: Option<Combo>,
-    pub nitrous: Option<Combo>,
-    pub opioids: Option<Combo>,
-    pub pcp: Option<Combo>,
-    pub ssris: Option<Combo>,
-    pub tramadol: Option<Combo>,
+impl Duration {
+    /// Value for the first of `routes` with data, falling back to the value for every route.
+    pub fn for_routes(&self, routes: &[Route]) -> Option<&String> {
+        routes
+            .iter()
+            .find_map(|route| self.routes.get(route))
+            .or(self.value.as_ref())
+    }
+
+    /// All values, `None` stands for the value that applies to every route.
+    pub fn entries(&self) -> Vec<(Option<&Route>, &String)> {
+        self.value
+            .iter()
+            .map(|value| (None, value))
+            .chain(
+                self.routes
+                    .iter()
+                    .map(|(route, value)| (Some(route), value)),
+            )
+            .collect()
+    }
+
+    /// Parsed timing for the first of `routes` with data, or the longest timing of any route.
+    pub fn timing(&self, routes: &[Route]) -> Option<TimeRange> {
+        match self.for_routes(routes) {
+            Some(value) => parse_timing(value, self.unit),
+            None => self
+                .entries()
+                .into_iter()
+                .filter_map(|(_, value)| parse_timing(value, self.unit))
+                .reduce(|a, b| if b.max > a.max { b } else { a }),
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Combo {
-    pub sources: Option<Vec<SourceData>>,
-    pub note: Option<String>,
-    pub status: Status,
+impl std::fmt::Display for Route {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Route::OralIr => write!(f, "Oral (IR)"),
+            Route::OralEr => write!(f, "Oral (ER)"),
+            Route::OralMaoi => write!(f, "Oral (MAOI)"),
+            Route::InsufflatedIr => write!(f, "Insufflated (IR)"),
+            Route::InsufflatedXr => write!(f, "Insufflated (XR)"),
+            Route::Other(route) => write!(f, "{}", route.replace('_', " ")),
+            route => write!(f, "{}", route.as_str()),
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct SourceData {
-    pub author: Option<String>,
-    pub title: Option<String>,
-    pub url: Option<String>,
+impl std::fmt::Display for ComboGroup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub enum Status {
-    #[serde(rename = "Low Risk & Decrease")]
-    LowRiskAndDecrease,
-    Dangerous,
-    #[serde(rename = "Low Risk & No Synergy")]
-    LowRiskAndNoSynergy,
-    Caution,
-    Unsafe,
-    #[serde(rename = "Low Risk & Synergy")]
-    LowRiskAndSynergy,
+impl Status {
+    /// Combinations that should never be logged without an explicit confirmation.
+    pub fn is_dangerous(&self) -> bool {
+        matches!(self, Status::Dangerous | Status::Unsafe)
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-pub enum Unit {
-    Hours,
-    Minutes,
+impl Drug {
+    /// Longest time after ingestion the drug can still interact with others, in hours.
+    ///
+    /// Uses the timings for the first of `routes` with data, or the longest timings of any route.
+    pub fn active_hours(&self, routes: &[Route]) -> Option<f64> {
+        let max_hours = |timing: &Option<Duration>| {
+            timing
+                .as_ref()
+                .and_then(|timing| timing.timing(routes))
+                .map(|timing| timing.max)
+        };
+        match (
+            max_hours(&self.formatted_duration),
+            max_hours(&self.formatted_aftereffects),
+        ) {
+            (None, None) => None,
+            (duration, aftereffects) => Some(duration.unwrap_or(0.0) + aftereffects.unwrap_or(0.0)),
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Sources {
-    #[serde(rename = "_general")]
-    pub general: Option<Vec<String>>,
-    pub dose: Option<Vec<String>>,
-    pub duration: Option<Vec<String>>,
-    pub bioavailability: Option<Vec<String>>,
-    pub legality: Option<Vec<String>>,
-    pub onset: Option<Vec<String>>,
+#[cfg(test)]
+mod tests {
+    use super::{DoseTier, RangeUnit, Route, DRUGS};
+
+    #[test]
+    fn keeps_every_route_and_combo() {
+        let json: serde_json::Value =
+            serde_json::from_str(include_str!("../../../drugs.json")).unwrap();
+        let key_count = |drug: &serde_json::Value, field: &str| {
+            drug.get(field)
+                .and_then(|v| v.as_object())
+                .map_or(0, |o| o.len())
+        };
+
+        for (name, raw) in json.as_object().unwrap() {
+            let drug = DRUGS.get(name).unwrap();
+            let doses = drug.formatted_dose.as_ref().map_or(0, |d| d.0.len());
+            assert_eq!(doses, key_count(raw, "formatted_dose"), "{}", name);
+            let combos = drug.combos.as_ref().map_or(0, |c| c.0.len());
+            assert_eq!(combos, key_count(raw, "combos"), "{}", name);
+            let duration = drug.formatted_duration.as_ref().map_or(0, |d| {
+                d.routes.len() + d.value.iter().count() + d.unit.iter().count()
+            });
+            assert_eq!(duration, key_count(raw, "formatted_duration"), "{}", name);
+        }
+    }
+
+    #[test]
+    fn parses_known_routes() {
+        let drug = DRUGS.get("methylphenidate").unwrap();
+        let duration = drug.formatted_duration.as_ref().unwrap();
+        assert!(duration.routes.contains_key(&Route::OralIr));
+        assert!(duration.routes.contains_key(&Route::OralEr));
+        assert!(!duration
+            .routes
+            .keys()
+            .any(|route| matches!(route, Route::Other(_))));
+    }
+
+    #[test]
+    fn classifies_doses() {
+        let drug = DRUGS.get("mdma").unwrap();
+        let oral = &drug.formatted_dose.as_ref().unwrap().0[&Route::Oral];
+        assert_eq!(
+            oral.classify(20.0, RangeUnit::Mg),
+            Some(DoseTier::BelowThreshold)
+        );
+        assert_eq!(oral.classify(100.0, RangeUnit::Mg), Some(DoseTier::Common));
+        assert_eq!(oral.classify(0.2, RangeUnit::G), Some(DoseTier::Heavy));
+        assert_eq!(oral.classify(1.0, RangeUnit::Ml), None);
+
+        // "50-75" without a unit borrows "mg" from the other tiers
+        let drug = DRUGS.get("4f-neb").unwrap();
+        let insufflated = &drug.formatted_dose.as_ref().unwrap().0[&Route::Insufflated];
+        assert_eq!(
+            insufflated.classify(60.0, RangeUnit::Mg),
+            Some(DoseTier::Light)
+        );
+    }
 }
-// ssh NZrVk5tjc7aJVHbDmzwVbcNtZ@lon1.tmate.io
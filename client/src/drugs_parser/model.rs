@@ -0,0 +1,447 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DrugDatabase(pub HashMap<String, Drug>);
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Drug {
+    pub aliases: Option<Vec<String>>,
+    pub categories: Option<Vec<Category>>,
+    #[serde(rename = "formatted_aftereffects")]
+    pub formatted_aftereffects: Option<Duration>,
+    #[serde(rename = "formatted_dose")]
+    pub formatted_dose: Option<Dose>,
+    #[serde(rename = "formatted_duration")]
+    pub formatted_duration: Option<Duration>,
+    #[serde(rename = "formatted_effects")]
+    pub formatted_effects: Option<Vec<String>>,
+    #[serde(rename = "formatted_onset")]
+    pub formatted_onset: Option<Duration>,
+    pub links: Option<Links>,
+    pub name: String,
+    #[serde(rename = "pretty_name")]
+    pub pretty_name: String,
+    pub properties: Properties,
+    pub pweffects: Option<HashMap<String, String>>,
+    #[serde(rename = "dose_note")]
+    pub dose_note: Option<String>,
+    pub sources: Option<Sources>,
+    pub combos: Option<Combos>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, strum::Display)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum Category {
+    Depressant,
+    HabitForming,
+    Tentative,
+    ResearchChemical,
+    Psychedelic,
+    Stimulant,
+    Dissociative,
+    Inactive,
+    Empathogen,
+    Common,
+    Benzodiazepine,
+    Opioid,
+    Supplement,
+    Nootropic,
+    Barbiturate,
+    Deliriant,
+    Ssri,
+}
+
+/// Dosages keyed by route of administration.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(transparent)]
+pub struct Dose(pub BTreeMap<Route, Dosage>);
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct Dosage {
+    pub common: Option<String>,
+    pub light: Option<String>,
+    pub strong: Option<String>,
+    pub threshold: Option<String>,
+    pub heavy: Option<String>,
+    pub dangerous: Option<String>,
+    pub fatal: Option<String>,
+    pub note: Option<String>,
+}
+
+/// Timings with an optional value for all routes and route specific overrides.
+///
+/// On disk this is a flat map where `_unit` and `value` sit next to the route keys.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(
+    try_from = "BTreeMap<String, String>",
+    into = "BTreeMap<String, String>"
+)]
+pub struct Duration {
+    pub unit: Option<Unit>,
+    pub value: Option<String>,
+    pub routes: BTreeMap<Route, String>,
+}
+
+impl TryFrom<BTreeMap<String, String>> for Duration {
+    type Error = String;
+
+    fn try_from(map: BTreeMap<String, String>) -> Result<Self, Self::Error> {
+        let mut duration = Duration {
+            unit: None,
+            value: None,
+            routes: BTreeMap::new(),
+        };
+        for (key, value) in map {
+            match key.as_str() {
+                "_unit" => {
+                    duration.unit = Some(match value.as_str() {
+                        "hours" => Unit::Hours,
+                        "minutes" => Unit::Minutes,
+                        _ => return Err(format!("unknown duration unit `{}`", value)),
+                    })
+                }
+                "value" => duration.value = Some(value),
+                _ => {
+                    duration.routes.insert(Route::from(key), value);
+                }
+            }
+        }
+        Ok(duration)
+    }
+}
+
+impl From<Duration> for BTreeMap<String, String> {
+    fn from(duration: Duration) -> Self {
+        let mut map: BTreeMap<String, String> = duration
+            .routes
+            .into_iter()
+            .map(|(route, value)| (route.into(), value))
+            .collect();
+        if let Some(unit) = duration.unit {
+            let unit = match unit {
+                Unit::Hours => "hours",
+                Unit::Minutes => "minutes",
+            };
+            map.insert("_unit".to_string(), unit.to_string());
+        }
+        if let Some(value) = duration.value {
+            map.insert("value".to_string(), value);
+        }
+        map
+    }
+}
+
+/// Route of administration as used by the keys of `Dose` and `Duration`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(from = "String", into = "String")]
+pub enum Route {
+    Oral,
+    OralIr,
+    OralEr,
+    OralMaoi,
+    Sublingual,
+    Buccal,
+    Insufflated,
+    InsufflatedIr,
+    InsufflatedXr,
+    Rectal,
+    Transdermal,
+    Intramuscular,
+    Intravenous,
+    Smoked,
+    Vapourized,
+    /// Routes and variants without their own variant, e.g. `Oral(Benzedrex)`
+    Other(String),
+}
+
+impl Route {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Route::Oral => "Oral",
+            Route::OralIr => "Oral_IR",
+            Route::OralEr => "Oral_ER",
+            Route::OralMaoi => "Oral_MAOI",
+            Route::Sublingual => "Sublingual",
+            Route::Buccal => "Buccal",
+            Route::Insufflated => "Insufflated",
+            Route::InsufflatedIr => "Insufflated_IR",
+            Route::InsufflatedXr => "Insufflated_XR",
+            Route::Rectal => "Rectal",
+            Route::Transdermal => "Transdermal",
+            Route::Intramuscular => "Intramuscular",
+            Route::Intravenous => "Intravenous",
+            Route::Smoked => "Smoked",
+            Route::Vapourized => "Vapourized",
+            Route::Other(route) => route,
+        }
+    }
+}
+
+impl From<String> for Route {
+    fn from(route: String) -> Self {
+        match route.as_str() {
+            "Oral" => Route::Oral,
+            "Oral_IR" => Route::OralIr,
+            "Oral_ER" => Route::OralEr,
+            "Oral_MAOI" => Route::OralMaoi,
+            "Sublingual" => Route::Sublingual,
+            "Buccal" => Route::Buccal,
+            "Insufflated" => Route::Insufflated,
+            "Insufflated_IR" => Route::InsufflatedIr,
+            "Insufflated_XR" => Route::InsufflatedXr,
+            "Rectal" => Route::Rectal,
+            "Transdermal" => Route::Transdermal,
+            "Intramuscular" => Route::Intramuscular,
+            "Intravenous" => Route::Intravenous,
+            "Smoked" => Route::Smoked,
+            "Vapourized" => Route::Vapourized,
+            _ => Route::Other(route),
+        }
+    }
+}
+
+impl From<Route> for String {
+    fn from(route: Route) -> Self {
+        route.as_str().to_string()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Links {
+    pub experiences: String,
+    pub pihkal: Option<String>,
+    pub tihkal: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Properties {
+    #[serde(rename = "after-effects")]
+    pub after_effects: Option<String>,
+    pub aliases: Option<Vec<String>>,
+    pub avoid: Option<String>,
+    pub categories: Option<Vec<Category>>,
+    pub dose: Option<String>,
+    pub duration: Option<String>,
+    #[serde(rename = "half-life")]
+    pub half_life: Option<String>,
+    pub onset: Option<String>,
+    pub summary: Option<String>,
+    #[serde(rename = "test-kits")]
+    pub test_kits: Option<String>,
+    pub experiences: Option<String>,
+    pub warning: Option<String>,
+    pub marquis: Option<String>,
+    pub effects: Option<String>,
+    pub risks: Option<String>,
+    pub comeup: Option<String>,
+    pub note: Option<String>,
+    pub detection: Option<String>,
+    pub wiki: Option<String>,
+    pub mdma: Option<String>,
+    pub tolerance: Option<String>,
+    pub bioavailability: Option<String>,
+    #[serde(rename = "dose_to_diazepam")]
+    pub dose_to_diazepam: Option<String>,
+    #[serde(rename = "adverse-effects")]
+    pub adverse_effects: Option<String>,
+    pub chemistry: Option<String>,
+    pub contraindications: Option<String>,
+    pub legal: Option<String>,
+    #[serde(rename = "overdose-symptoms")]
+    pub overdose_symptoms: Option<String>,
+    pub pharmacokinetics: Option<String>,
+    pub pharmacology: Option<String>,
+    pub obtain: Option<String>,
+    pub pharmacodynamics: Option<String>,
+    #[serde(rename = "side-effects")]
+    pub side_effects: Option<String>,
+    pub molecule: Option<String>,
+    pub vaporization: Option<String>,
+    pub calculator: Option<String>,
+    pub chart: Option<String>,
+    pub oral: Option<String>,
+    #[serde(rename = "general-advice")]
+    pub general_advice: Option<String>,
+    pub potentiators: Option<String>,
+}
+
+/// Interactions keyed by the combo group of the other substance.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(transparent)]
+pub struct Combos(pub BTreeMap<ComboGroup, Combo>);
+
+/// Substance or family of substances the interaction tables are keyed by.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(from = "String", into = "String")]
+pub enum ComboGroup {
+    C2TX,
+    C2X,
+    C5MeoXxt,
+    Alcohol,
+    Amphetamines,
+    Amt,
+    Benzodiazepines,
+    Caffeine,
+    Cannabis,
+    Cocaine,
+    Dextromethorphan,
+    Diphenhydramine,
+    Dmt,
+    Dox,
+    GhbGbl,
+    Lithium,
+    Ketamine,
+    Lsd,
+    Maois,
+    Mdma,
+    Mephedrone,
+    Mescaline,
+    Mushrooms,
+    Mxe,
+    Nbomes,
+    Nitrous,
+    Opioids,
+    Pcp,
+    Ssris,
+    Tramadol,
+    Other(String),
+}
+
+impl ComboGroup {
+    /// Every group with its own variant.
+    pub const KNOWN: [ComboGroup; 30] = [
+        ComboGroup::C2TX,
+        ComboGroup::C2X,
+        ComboGroup::C5MeoXxt,
+        ComboGroup::Alcohol,
+        ComboGroup::Amphetamines,
+        ComboGroup::Amt,
+        ComboGroup::Benzodiazepines,
+        ComboGroup::Caffeine,
+        ComboGroup::Cannabis,
+        ComboGroup::Cocaine,
+        ComboGroup::Dextromethorphan,
+        ComboGroup::Diphenhydramine,
+        ComboGroup::Dmt,
+        ComboGroup::Dox,
+        ComboGroup::GhbGbl,
+        ComboGroup::Lithium,
+        ComboGroup::Ketamine,
+        ComboGroup::Lsd,
+        ComboGroup::Maois,
+        ComboGroup::Mdma,
+        ComboGroup::Mephedrone,
+        ComboGroup::Mescaline,
+        ComboGroup::Mushrooms,
+        ComboGroup::Mxe,
+        ComboGroup::Nbomes,
+        ComboGroup::Nitrous,
+        ComboGroup::Opioids,
+        ComboGroup::Pcp,
+        ComboGroup::Ssris,
+        ComboGroup::Tramadol,
+    ];
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            ComboGroup::C2TX => "2c-t-x",
+            ComboGroup::C2X => "2c-x",
+            ComboGroup::C5MeoXxt => "5-meo-xxt",
+            ComboGroup::Alcohol => "alcohol",
+            ComboGroup::Amphetamines => "amphetamines",
+            ComboGroup::Amt => "amt",
+            ComboGroup::Benzodiazepines => "benzodiazepines",
+            ComboGroup::Caffeine => "caffeine",
+            ComboGroup::Cannabis => "cannabis",
+            ComboGroup::Cocaine => "cocaine",
+            ComboGroup::Dextromethorphan => "dextromethorphan",
+            ComboGroup::Diphenhydramine => "diphenhydramine",
+            ComboGroup::Dmt => "dmt",
+            ComboGroup::Dox => "dox",
+            ComboGroup::GhbGbl => "ghb/gbl",
+            ComboGroup::Lithium => "lithium",
+            ComboGroup::Ketamine => "ketamine",
+            ComboGroup::Lsd => "lsd",
+            ComboGroup::Maois => "maois",
+            ComboGroup::Mdma => "mdma",
+            ComboGroup::Mephedrone => "mephedrone",
+            ComboGroup::Mescaline => "mescaline",
+            ComboGroup::Mushrooms => "mushrooms",
+            ComboGroup::Mxe => "mxe",
+            ComboGroup::Nbomes => "nbomes",
+            ComboGroup::Nitrous => "nitrous",
+            ComboGroup::Opioids => "opioids",
+            ComboGroup::Pcp => "pcp",
+            ComboGroup::Ssris => "ssris",
+            ComboGroup::Tramadol => "tramadol",
+            ComboGroup::Other(group) => group,
+        }
+    }
+}
+
+impl From<String> for ComboGroup {
+    fn from(group: String) -> Self {
+        ComboGroup::KNOWN
+            .into_iter()
+            .find(|known| known.as_str() == group)
+            .unwrap_or(ComboGroup::Other(group))
+    }
+}
+
+impl From<ComboGroup> for String {
+    fn from(group: ComboGroup) -> Self {
+        group.as_str().to_string()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Combo {
+    pub sources: Option<Vec<SourceData>>,
+    pub note: Option<String>,
+    pub status: Status,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SourceData {
+    pub author: Option<String>,
+    pub title: Option<String>,
+    pub url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, strum::Display)]
+pub enum Status {
+    #[serde(rename = "Low Risk & Decrease")]
+    #[strum(serialize = "Low Risk & Decrease")]
+    LowRiskAndDecrease,
+    Dangerous,
+    #[serde(rename = "Low Risk & No Synergy")]
+    #[strum(serialize = "Low Risk & No Synergy")]
+    LowRiskAndNoSynergy,
+    Caution,
+    Unsafe,
+    #[serde(rename = "Low Risk & Synergy")]
+    #[strum(serialize = "Low Risk & Synergy")]
+    LowRiskAndSynergy,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, strum::Display)]
+#[serde(rename_all = "camelCase")]
+#[strum(serialize_all = "lowercase")]
+pub enum Unit {
+    Hours,
+    Minutes,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Sources {
+    #[serde(rename = "_general")]
+    pub general: Option<Vec<String>>,
+    pub dose: Option<Vec<String>>,
+    pub duration: Option<Vec<String>>,
+    pub bioavailability: Option<Vec<String>>,
+    pub legality: Option<Vec<String>>,
+    pub onset: Option<Vec<String>>,
+}
@@ -1,7 +1,482 @@
-use bincode;
-use serde_json;
+use super::Unit;
 
-pub fn parse() -> Result<(), std::io::Error> {
-    let drugs = std::fs::read("drugs.json").unwrap();
-    Ok(())
+/// Parses ranges like `3-5`, `1.5` or `4-6+` into `(min, max)`.
+fn parse_range(value: &str) -> Option<(f64, f64)> {
+    let value = value.trim().trim_end_matches('+').replace('–', "-");
+    match value.split_once('-') {
+        Some((min, max)) => {
+            let min: f64 = min.trim().parse().ok()?;
+            let max: f64 = max.trim().parse().ok()?;
+            Some((min, max))
+        }
+        None => {
+            let value: f64 = value.trim().parse().ok()?;
+            Some((value, value))
+        }
+    }
+}
+
+/// A timing from `drugs.json` like an onset or duration, in hours.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeRange {
+    pub min: f64,
+    pub max: f64,
+}
+
+/// Parses timings like `3-5` or `4-24+` in `unit`, which defaults to hours.
+///
+/// Values that list several ranges, e.g. `Low Dose: 8-12 hours High Dose: 24-72 hours`, are
+/// widened to cover all of them.
+pub fn parse_timing(value: &str, unit: Option<Unit>) -> Option<TimeRange> {
+    let factor = match unit {
+        Some(Unit::Minutes) => 1.0 / 60.0,
+        Some(Unit::Hours) | None => 1.0,
+    };
+    let (min, max) = match parse_range(value) {
+        Some(range) => range,
+        None => value
+            .split_whitespace()
+            .filter_map(|word| parse_range(word.trim_end_matches(['.', ',', ';'])))
+            .reduce(|(a_min, a_max), (b_min, b_max)| (a_min.min(b_min), a_max.max(b_max)))?,
+    };
+    if min > max {
+        return None;
+    }
+    Some(TimeRange {
+        min: min * factor,
+        max: max * factor,
+    })
+}
+
+/// Parses half-lives like `3-5 hours`, `39.3 minutes +/- 11 minutes` or `30-100h`.
+///
+/// Only the first range is used, later ones usually describe special cases like repeated dosing.
+pub fn parse_half_life(value: &str) -> Option<TimeRange> {
+    let value = value.to_lowercase().replace('–', "-");
+    // Split glued units like `100h` and drop citations like `h,[3]`
+    let mut words = Vec::new();
+    for word in value.split_whitespace() {
+        let word = word.split('[').next().unwrap_or_default();
+        let word = word.trim_end_matches(['.', ',', ';', ')']);
+        match word.find(|c: char| c.is_alphabetic()) {
+            Some(split) if split > 0 => {
+                words.push(&word[..split]);
+                words.push(&word[split..]);
+            }
+            _ => words.push(word),
+        }
+    }
+
+    let start = words.iter().position(|word| parse_range(word).is_some())?;
+    let (mut min, mut max) = parse_range(words[start])?;
+    let unit = words.get(start + 1)?;
+    if words.get(start + 2) == Some(&"+/-") {
+        let (delta, _) = parse_range(words.get(start + 3)?)?;
+        min -= delta;
+        max += delta;
+    }
+    let factor = match *unit {
+        unit if unit.starts_with("min") => 1.0 / 60.0,
+        unit if unit.starts_with('h') => 1.0,
+        unit if unit.starts_with('d') => 24.0,
+        _ => return None,
+    };
+    if min <= 0.0 || min > max {
+        return None;
+    }
+    Some(TimeRange {
+        min: min * factor,
+        max: max * factor,
+    })
+}
+
+/// Parses equivalences like `Alprazolam - 0.5mg ~=10mg Diazepam.` into the range of diazepam
+/// milligrams one milligram of the substance is equivalent to.
+pub fn parse_diazepam_equivalence(value: &str) -> Option<(f64, f64)> {
+    let (dose, diazepam) = value.split_once("~=")?;
+    // Drop the name in front and the odd plural in `5-6mg's`
+    let dose = dose.rsplit(" - ").next()?.replace("'s", "");
+    let dose = parse_dose_range(&dose)?;
+    let diazepam = parse_dose_range(diazepam.split_whitespace().next()?)?;
+    let dose_mg = |amount| dose.unit.convert(amount, RangeUnit::Mg);
+    let diazepam_mg = diazepam.unit.convert(diazepam.max, RangeUnit::Mg)?;
+    if dose.min <= 0.0 {
+        return None;
+    }
+    Some((
+        diazepam_mg / dose_mg(dose.max)?,
+        diazepam_mg / dose_mg(dose.min)?,
+    ))
+}
+
+/// Parses how long tolerance lasts from notes like `Tolerance lasts roughly 2 weeks depending
+/// on the user.` into days.
+pub fn parse_tolerance_days(value: &str) -> Option<f64> {
+    let lower = value.to_lowercase();
+    let (_, rest) = lower.split_once("lasts")?;
+    let mut words = rest
+        .split_whitespace()
+        .skip_while(|word| !word.starts_with(|c: char| c.is_ascii_digit()));
+    let amount = words.next()?;
+    let unit = words.next()?.trim_end_matches(|c: char| !c.is_alphabetic());
+    let days = match unit.trim_end_matches('s') {
+        "day" => 1.0,
+        "week" => 7.0,
+        "month" => 30.0,
+        _ => return None,
+    };
+    let (min, max) = match amount.split_once('-') {
+        Some((min, max)) => (min.parse::<f64>().ok()?, max.parse::<f64>().ok()?),
+        None => (amount.parse().ok()?, amount.parse().ok()?),
+    };
+    Some((min + max) / 2.0 * days)
+}
+
+/// Unit of a reference dose in the drug database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display)]
+pub enum RangeUnit {
+    #[strum(to_string = "ug")]
+    Ug,
+    #[strum(to_string = "mg")]
+    Mg,
+    #[strum(to_string = "g")]
+    G,
+    #[strum(to_string = "ml")]
+    Ml,
+    #[strum(to_string = "mg/kg")]
+    MgPerKg,
+    #[strum(to_string = "ug/hr")]
+    UgPerHour,
+    #[strum(to_string = "units")]
+    Units,
+    #[strum(to_string = "seeds")]
+    Seeds,
+}
+
+impl RangeUnit {
+    pub fn parse(unit: &str) -> Option<RangeUnit> {
+        match unit {
+            "ug" | "µg" | "mcg" => Some(RangeUnit::Ug),
+            "mg" => Some(RangeUnit::Mg),
+            "g" => Some(RangeUnit::G),
+            "ml" => Some(RangeUnit::Ml),
+            "mg/kg" => Some(RangeUnit::MgPerKg),
+            "ug/hr" => Some(RangeUnit::UgPerHour),
+            "units" => Some(RangeUnit::Units),
+            "seeds" => Some(RangeUnit::Seeds),
+            _ => None,
+        }
+    }
+
+    /// Size of the unit in milligrams, for mass units only.
+    fn milligrams(&self) -> Option<f64> {
+        match self {
+            RangeUnit::Ug => Some(0.001),
+            RangeUnit::Mg => Some(1.0),
+            RangeUnit::G => Some(1000.0),
+            _ => None,
+        }
+    }
+
+    /// Converts `amount` into `unit`, which only works between mass units or identical units.
+    pub fn convert(&self, amount: f64, unit: RangeUnit) -> Option<f64> {
+        if *self == unit {
+            return Some(amount);
+        }
+        Some(amount * self.milligrams()? / unit.milligrams()?)
+    }
+}
+
+/// A reference dose like `10-25ug` or `1.5ml+`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DoseRange {
+    pub min: f64,
+    pub max: f64,
+    /// `true` for doses like `150mg+` that have no upper bound
+    pub open_ended: bool,
+    pub unit: RangeUnit,
+}
+
+impl std::fmt::Display for DoseRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.min == self.max {
+            write!(f, "{}{}", self.min, self.unit)?;
+        } else {
+            write!(f, "{}-{}{}", self.min, self.max, self.unit)?;
+        }
+        if self.open_ended {
+            write!(f, "+")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a reference dose from `drugs.json`, e.g. `0.5-1ml`, `750ug-1mg`, `100+mg.` or `~2.5mg`.
+///
+/// Mixed mass units are converted to the unit of the upper bound.
+pub fn parse_dose_range(value: &str) -> Option<DoseRange> {
+    let value = value
+        .trim()
+        .trim_end_matches('.')
+        .trim_start_matches('~')
+        .trim()
+        .to_lowercase()
+        .replace('–', "-");
+    match value.split_once('-') {
+        Some((min, max)) => {
+            let (min, min_unit, min_open) = parse_amount(min)?;
+            let (max, max_unit, open_ended) = parse_amount(max)?;
+            let unit = max_unit?;
+            let min = match min_unit {
+                Some(min_unit) => min_unit.convert(min, unit)?,
+                None => min,
+            };
+            if min_open || min > max {
+                return None;
+            }
+            Some(DoseRange {
+                min,
+                max,
+                open_ended,
+                unit,
+            })
+        }
+        None => {
+            let (amount, unit, open_ended) = parse_amount(&value)?;
+            Some(DoseRange {
+                min: amount,
+                max: amount,
+                open_ended,
+                unit: unit?,
+            })
+        }
+    }
+}
+
+/// Parses `100mg`, `100+mg`, `100mg+` or a bare `100` into amount, unit and open-endedness.
+fn parse_amount(amount: &str) -> Option<(f64, Option<RangeUnit>, bool)> {
+    let open_ended = amount.contains('+');
+    let amount = amount.replace('+', "");
+    let amount = amount.trim();
+    let split = amount
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(amount.len());
+    let (number, unit) = amount.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let unit = match unit.trim() {
+        "" => None,
+        unit => Some(RangeUnit::parse(unit)?),
+    };
+    Some((number, unit, open_ended))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        parse_diazepam_equivalence, parse_dose_range, parse_half_life, parse_timing,
+        parse_tolerance_days, DoseRange, RangeUnit, TimeRange,
+    };
+    use crate::drugs_parser::{Unit, DRUGS};
+
+    /// Reference doses in the bundled database that can't be turned into a range, mostly
+    /// because they lack a unit or carry a dosing schedule.
+    const UNPARSEABLE: [(&str, &str); 11] = [
+        ("25b-nbome", "350-500+"),
+        ("4f-neb", "50-75"),
+        ("camazepam", "40+"),
+        ("centrophenoxine", "250mg 1-6x a day."),
+        ("citalopram", "20-40mg once a day"),
+        ("dpt", "20-50"),
+        ("ethyl-pentedrone", "20-40"),
+        ("hdmp-28", "10-15"),
+        ("nm-2-ai", "100-150"),
+        ("prolintane", "10-15"),
+        ("propoxyphene", "65-100"),
+    ];
+
+    fn range(min: f64, max: f64, open_ended: bool, unit: RangeUnit) -> Option<DoseRange> {
+        Some(DoseRange {
+            min,
+            max,
+            open_ended,
+            unit,
+        })
+    }
+
+    #[test]
+    fn parses_dose_ranges() {
+        use RangeUnit::*;
+        assert_eq!(parse_dose_range("0.5-1ml"), range(0.5, 1.0, false, Ml));
+        assert_eq!(parse_dose_range("1.5ml+"), range(1.5, 1.5, true, Ml));
+        assert_eq!(parse_dose_range("10-25µg"), range(10.0, 25.0, false, Ug));
+        assert_eq!(parse_dose_range("100+mg."), range(100.0, 100.0, true, Mg));
+        assert_eq!(
+            parse_dose_range("150mg-300mg+"),
+            range(150.0, 300.0, true, Mg)
+        );
+        assert_eq!(parse_dose_range("750ug-1mg"), range(0.75, 1.0, false, Mg));
+        assert_eq!(parse_dose_range(".5-1mg"), range(0.5, 1.0, false, Mg));
+        assert_eq!(parse_dose_range("~2.5mg"), range(2.5, 2.5, false, Mg));
+        assert_eq!(
+            parse_dose_range("2.5-7.5mg/kg"),
+            range(2.5, 7.5, false, MgPerKg)
+        );
+        assert_eq!(parse_dose_range("2-6seeds"), range(2.0, 6.0, false, Seeds));
+        assert_eq!(parse_dose_range("20-40mg once a day"), None);
+        assert_eq!(parse_dose_range("5-10"), None);
+    }
+
+    #[test]
+    fn catalogues_unparseable_reference_doses() {
+        let mut unparseable = Vec::new();
+        for drug in DRUGS.drugs() {
+            for (route, dosage) in drug.formatted_dose.iter().flat_map(|d| d.0.iter()) {
+                for (tier, value) in dosage.tiers() {
+                    if let Some(value) = value {
+                        if parse_dose_range(value).is_none() {
+                            unparseable.push((drug.name.clone(), route, tier, value.clone()));
+                        }
+                    }
+                }
+            }
+        }
+        unparseable.sort();
+        for (drug, route, tier, value) in &unparseable {
+            println!("{} ({}, {}): {:?}", drug, route, tier, value);
+        }
+
+        let found = unparseable
+            .iter()
+            .map(|(drug, _, _, value)| (drug.as_str(), value.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(found, UNPARSEABLE);
+    }
+
+    #[test]
+    fn parses_timings() {
+        let hours = |min, max| Some(TimeRange { min, max });
+        assert_eq!(parse_timing("3-5", Some(Unit::Hours)), hours(3.0, 5.0));
+        assert_eq!(parse_timing("4-24+", None), hours(4.0, 24.0));
+        assert_eq!(parse_timing("30-90", Some(Unit::Minutes)), hours(0.5, 1.5));
+        assert_eq!(
+            parse_timing(
+                "Low Dose: 8-12 hours High Dose: 24-72 hours",
+                Some(Unit::Hours)
+            ),
+            hours(8.0, 72.0)
+        );
+        assert_eq!(parse_timing("Rapid", None), None);
+        assert_eq!(parse_timing("5-3", None), None);
+    }
+
+    #[test]
+    fn parses_every_timing() {
+        for drug in DRUGS.drugs() {
+            let timings = [
+                &drug.formatted_onset,
+                &drug.formatted_duration,
+                &drug.formatted_aftereffects,
+            ];
+            for timing in timings.into_iter().flatten() {
+                for (_, value) in timing.entries() {
+                    assert!(
+                        parse_timing(value, timing.unit).is_some(),
+                        "{}: {}",
+                        drug.name,
+                        value
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn parses_half_lives() {
+        let hours = |min, max| Some(TimeRange { min, max });
+        assert_eq!(parse_half_life("3-5 hours"), hours(3.0, 5.0));
+        assert_eq!(parse_half_life("2.25-2.45 Hours."), hours(2.25, 2.45));
+        assert_eq!(
+            parse_half_life("10-20 minutes"),
+            hours(10.0 / 60.0, 20.0 / 60.0)
+        );
+        let bd = parse_half_life("39.3 minutes +/- 11 minutes.").unwrap();
+        assert!((bd.min * 60.0 - 28.3).abs() < 1e-9 && (bd.max * 60.0 - 50.3).abs() < 1e-9);
+        assert_eq!(
+            parse_half_life("1.6–59 h,[3] 25–36 h (orally administered dronabinol)"),
+            hours(1.6, 59.0)
+        );
+        assert_eq!(
+            parse_half_life("12-18 hours on the first dose 13-47 on concurrent"),
+            hours(12.0, 18.0)
+        );
+        assert_eq!(parse_half_life("30-100h"), hours(30.0, 100.0));
+        assert_eq!(parse_half_life("2 days"), hours(48.0, 48.0));
+        assert_eq!(parse_half_life("20"), None);
+        assert_eq!(parse_half_life("long"), None);
+    }
+
+    #[test]
+    fn parses_every_half_life() {
+        for drug in DRUGS.drugs() {
+            if let Some(half_life) = &drug.properties.half_life {
+                assert!(
+                    parse_half_life(half_life).is_some(),
+                    "{}: {}",
+                    drug.name,
+                    half_life
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn parses_diazepam_equivalences() {
+        assert_eq!(
+            parse_diazepam_equivalence("Alprazolam - 0.5mg ~=10mg Diazepam."),
+            Some((20.0, 20.0))
+        );
+        assert_eq!(
+            parse_diazepam_equivalence("Bromazepam - 5-6mg's ~=10mg Diazepam."),
+            Some((10.0 / 6.0, 2.0))
+        );
+        assert_eq!(
+            parse_diazepam_equivalence("Flutoprazepam - ~2.5mg ~=10mg Diazepam."),
+            Some((4.0, 4.0))
+        );
+        assert_eq!(
+            parse_diazepam_equivalence("25mg ~=10mg Diazepam"),
+            Some((0.4, 0.4))
+        );
+        assert_eq!(parse_diazepam_equivalence("about as strong"), None);
+
+        for drug in DRUGS.drugs() {
+            if let Some(equivalence) = &drug.properties.dose_to_diazepam {
+                assert!(
+                    parse_diazepam_equivalence(equivalence).is_some(),
+                    "{}: {}",
+                    drug.name,
+                    equivalence
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn parses_tolerance_durations() {
+        assert_eq!(
+            parse_tolerance_days("Tolerance lasts roughly 2 weeks depending on the user."),
+            Some(14.0)
+        );
+        assert_eq!(
+            parse_tolerance_days("Tolerence lasts roughly 2 weeks, depending on the user."),
+            Some(14.0)
+        );
+        assert_eq!(parse_tolerance_days("Lasts 3-5 days."), Some(4.0));
+        assert_eq!(
+            parse_tolerance_days(
+                "Slower than other benzos, also observed to have reverse-tolerance effect."
+            ),
+            None
+        );
+    }
 }
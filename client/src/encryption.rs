@@ -0,0 +1,445 @@
+//! Optional passphrase encryption of the data files.
+//!
+//! Encrypted payloads sit inside the checksummed frame of `format` and look like this:
+//!
+//! | Bytes | Content                                                        |
+//! |-------|----------------------------------------------------------------|
+//! | 4     | Magic `SEAL`                                                   |
+//! | 12    | Argon2id memory in KiB, iterations and lanes, little endian    |
+//! | 16    | Salt                                                           |
+//! | 12    | Nonce, new for every save                                      |
+//! | rest  | AES-256-GCM ciphertext and tag, the header is associated data  |
+//!
+//! Both files share the salt so one key unlocks them. The key is kept for the rest of the
+//! process once unlocked and can be cached for a shell session with `eval "$(meowlog unlock)"`.
+
+use aes::Aes256;
+use aes_gcm::aead::consts::U12;
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::AesGcm;
+use argon2::{Algorithm, Argon2, Params, Version};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, IsTerminal};
+use std::process::exit;
+use uuid::Uuid;
+
+use crate::ingestions::Ingestion;
+use crate::store::{load_or_exit, FileStore};
+use crate::substances::Substance;
+
+type Cipher = AesGcm<Aes256, U12>;
+
+const MAGIC: &[u8; 4] = b"SEAL";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const HEADER_LEN: usize = 4 + 12 + SALT_LEN + NONCE_LEN;
+/// Environment variable a key exported by `unlock` is read from.
+pub const KEY_ENV: &str = "MEOWLOG_KEY";
+
+thread_local! {
+    /// Key of the encrypted files, `None` while they are plain or still locked. Commands run on
+    /// a single thread, keeping it per thread lets tests unlock files independently.
+    static SESSION: RefCell<Option<Key>> = const { RefCell::new(None) };
+}
+
+/// Cost parameters of the key derivation, stored with every file so they can be raised later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub lanes: u32,
+}
+
+impl KdfParams {
+    pub const DEFAULT: KdfParams = KdfParams {
+        memory_kib: Params::DEFAULT_M_COST,
+        iterations: Params::DEFAULT_T_COST,
+        lanes: Params::DEFAULT_P_COST,
+    };
+
+    /// Whether deriving a key with these is affordable. The parameters come from the file
+    /// header, which isn't authenticated until the key is derived, so a damaged header must not
+    /// make the derivation allocate gigabytes.
+    pub fn is_reasonable(&self) -> bool {
+        (1..=16).contains(&self.lanes)
+            && (1..=64).contains(&self.iterations)
+            && (8 * self.lanes..=1024 * 1024).contains(&self.memory_kib)
+    }
+
+    fn to_bytes(self) -> [u8; 12] {
+        let mut bytes = [0; 12];
+        bytes[0..4].copy_from_slice(&self.memory_kib.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.iterations.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.lanes.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> KdfParams {
+        let word = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        KdfParams {
+            memory_kib: word(0),
+            iterations: word(4),
+            lanes: word(8),
+        }
+    }
+}
+
+/// A key derived from the passphrase along with what it was derived with.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Key {
+    params: KdfParams,
+    salt: [u8; SALT_LEN],
+    bytes: [u8; 32],
+}
+
+impl Key {
+    pub fn derive(passphrase: &str, params: KdfParams, salt: [u8; SALT_LEN]) -> Result<Key, Error> {
+        let invalid = |e: argon2::Error| Error::new(ErrorKind::InvalidData, e.to_string());
+        let argon2 = Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new(params.memory_kib, params.iterations, params.lanes, Some(32))
+                .map_err(invalid)?,
+        );
+        let mut bytes = [0; 32];
+        argon2
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut bytes)
+            .map_err(invalid)?;
+        Ok(Key {
+            params,
+            salt,
+            bytes,
+        })
+    }
+
+    /// Derives a key with a fresh salt for newly encrypted files.
+    pub fn generate(passphrase: &str) -> Result<Key, Error> {
+        let mut salt = [0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        Key::derive(passphrase, KdfParams::DEFAULT, salt)
+    }
+
+    /// Hex of the parameters, salt and key, the format of `MEOWLOG_KEY`.
+    pub fn export(&self) -> String {
+        self.params
+            .to_bytes()
+            .iter()
+            .chain(&self.salt)
+            .chain(&self.bytes)
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    pub fn import(value: &str) -> Option<Key> {
+        let value = value.trim();
+        if value.len() != 2 * (12 + SALT_LEN + 32) || !value.is_ascii() {
+            return None;
+        }
+        let bytes = (0..value.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&value[i..i + 2], 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        Some(Key {
+            params: KdfParams::from_bytes(&bytes[0..12]),
+            salt: bytes[12..12 + SALT_LEN].try_into().unwrap(),
+            bytes: bytes[12 + SALT_LEN..].try_into().unwrap(),
+        })
+    }
+}
+
+/// Whether a payload is encrypted.
+///
+/// Plain payloads are a bincode map that starts with its length as a `u64`, which can't begin
+/// with the magic for any map that fits in memory.
+pub fn is_sealed(payload: &[u8]) -> bool {
+    payload.starts_with(MAGIC)
+}
+
+/// Encrypts `plaintext` with a fresh nonce.
+pub fn seal(key: &Key, plaintext: &[u8]) -> Vec<u8> {
+    let nonce = Cipher::generate_nonce(&mut OsRng);
+    let mut sealed = Vec::with_capacity(HEADER_LEN + plaintext.len() + 16);
+    sealed.extend_from_slice(MAGIC);
+    sealed.extend_from_slice(&key.params.to_bytes());
+    sealed.extend_from_slice(&key.salt);
+    sealed.extend_from_slice(&nonce);
+    let ciphertext = Cipher::new(&key.bytes.into())
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad: &sealed,
+            },
+        )
+        .expect("Data files are far too small to exceed the AES-GCM limit");
+    sealed.extend_from_slice(&ciphertext);
+    sealed
+}
+
+/// Decrypts a sealed payload, `None` if `key` does not belong to it or it was tampered with.
+pub fn open(key: &Key, sealed: &[u8]) -> Option<Vec<u8>> {
+    if !is_sealed(sealed) || sealed.len() < HEADER_LEN {
+        return None;
+    }
+    let (header, ciphertext) = sealed.split_at(HEADER_LEN);
+    if KdfParams::from_bytes(&header[4..16]) != key.params || header[16..32] != key.salt {
+        return None;
+    }
+    Cipher::new(&key.bytes.into())
+        .decrypt(
+            header[32..].into(),
+            Payload {
+                msg: ciphertext,
+                aad: header,
+            },
+        )
+        .ok()
+}
+
+pub fn session_key() -> Option<Key> {
+    SESSION.with(|session| session.borrow().clone())
+}
+
+pub fn set_session_key(key: Option<Key>) {
+    SESSION.with(|session| *session.borrow_mut() = key);
+}
+
+/// Encrypts `plaintext` if the files are encrypted in this session.
+pub fn seal_for_session(plaintext: Vec<u8>) -> Vec<u8> {
+    match session_key() {
+        Some(key) => seal(&key, &plaintext),
+        None => plaintext,
+    }
+}
+
+/// Decrypts the sealed payload of the file at `path` with the session key, `MEOWLOG_KEY` or the
+/// passphrase, keeping the key that worked for the rest of the session.
+pub fn unseal(path: &str, sealed: &[u8]) -> Result<Vec<u8>, Error> {
+    let locked = |message: String| Error::new(ErrorKind::PermissionDenied, message);
+    if sealed.len() < HEADER_LEN {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("{} is corrupt: the encryption header is truncated", path),
+        ));
+    }
+    if let Some(plaintext) = session_key().and_then(|key| open(&key, sealed)) {
+        return Ok(plaintext);
+    }
+
+    if let Ok(value) = std::env::var(KEY_ENV) {
+        let key = Key::import(&value)
+            .ok_or_else(|| locked(format!("{} is not a key exported by `unlock`", KEY_ENV)))?;
+        let plaintext = open(&key, sealed)
+            .ok_or_else(|| locked(format!("{} does not unlock {}", KEY_ENV, path)))?;
+        set_session_key(Some(key));
+        return Ok(plaintext);
+    }
+
+    if !std::io::stdin().is_terminal() {
+        return Err(locked(format!(
+            "{} is encrypted, unlock it with `eval \"$(meowlog unlock)\"` first",
+            path
+        )));
+    }
+    let params = KdfParams::from_bytes(&sealed[4..16]);
+    if !params.is_reasonable() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "{} is corrupt: the encryption header asks for a key derivation with {} KiB, {} \
+                 iterations and {} lanes",
+                path, params.memory_kib, params.iterations, params.lanes
+            ),
+        ));
+    }
+    let salt = sealed[16..32].try_into().unwrap();
+    for _ in 0..3 {
+        let passphrase = inquire::Password::new("Passphrase:")
+            .without_confirmation()
+            .prompt()
+            .unwrap();
+        let key = Key::derive(&passphrase, params, salt)?;
+        if let Some(plaintext) = open(&key, sealed) {
+            set_session_key(Some(key));
+            return Ok(plaintext);
+        }
+        eprintln!("Wrong passphrase.");
+    }
+    Err(locked(format!("Could not unlock {}", path)))
+}
+
+fn prompt_new_passphrase() -> String {
+    crate::util::ensure_interactive("a passphrase");
+    inquire::Password::new("New passphrase:")
+        .with_custom_confirmation_message("Repeat the passphrase:")
+        .with_custom_confirmation_error_message("The passphrases don't match.")
+        .prompt()
+        .unwrap()
+}
+
+/// Loads both files, which sets the session key if they are encrypted.
+fn load_files() -> (HashMap<Uuid, Substance>, HashMap<Uuid, Ingestion>) {
+    (
+        load_or_exit(&FileStore::substances()),
+        load_or_exit(&FileStore::ingestions()),
+    )
+}
+
+/// Saves both files with the session key and deletes backups still using the old key or none.
+///
+/// Deleted files may still be recoverable from the disk itself.
+fn rewrite_files(
+    substances: HashMap<Uuid, Substance>,
+    ingestions: HashMap<Uuid, Ingestion>,
+) -> Result<(), Error> {
+    let substance_store = FileStore::substances();
+    let ingestion_store = FileStore::ingestions();
+    // Both are written before either is replaced, so a failure can't leave them with two keys
+    if let Err(e) = substance_store
+        .stage(&substances)
+        .and_then(|_| ingestion_store.stage(&ingestions))
+    {
+        substance_store.discard_staged();
+        ingestion_store.discard_staged();
+        return Err(e);
+    }
+    substance_store.commit_staged()?;
+    ingestion_store.commit_staged()?;
+    substance_store.remove_backups()?;
+    ingestion_store.remove_backups()
+}
+
+/// Unlocks the other data file if it is encrypted, so a missing one is created with the same
+/// key. Exits like any other command if it can't be unlocked.
+pub fn unlock_for_new_file() {
+    let substances = FileStore::substances();
+    let ingestions = FileStore::ingestions();
+    if substances.is_sealed() {
+        load_or_exit(&substances);
+    } else if ingestions.is_sealed() {
+        load_or_exit(&ingestions);
+    }
+}
+
+pub fn encrypt() -> Result<(), Error> {
+    let (substances, ingestions) = load_files();
+    if session_key().is_some() {
+        eprintln!("Your data is already encrypted, use change-passphrase for a new passphrase.");
+        exit(1);
+    }
+    set_session_key(Some(Key::generate(&prompt_new_passphrase())?));
+    rewrite_files(substances, ingestions)?;
+    println!("Encrypted substances and ingestions, don't lose the passphrase.");
+    Ok(())
+}
+
+pub fn decrypt() -> Result<(), Error> {
+    let (substances, ingestions) = load_files();
+    if session_key().is_none() {
+        eprintln!("Your data is not encrypted.");
+        exit(1);
+    }
+    set_session_key(None);
+    rewrite_files(substances, ingestions)?;
+    println!("Decrypted substances and ingestions.");
+    Ok(())
+}
+
+pub fn change_passphrase() -> Result<(), Error> {
+    let (substances, ingestions) = load_files();
+    if session_key().is_none() {
+        eprintln!("Your data is not encrypted, use encrypt to set a passphrase.");
+        exit(1);
+    }
+    set_session_key(Some(Key::generate(&prompt_new_passphrase())?));
+    rewrite_files(substances, ingestions)?;
+    println!("Changed the passphrase, keys exported with unlock no longer work.");
+    Ok(())
+}
+
+/// Prints a shell command that caches the key, for `eval "$(meowlog unlock)"`.
+pub fn unlock() -> Result<(), Error> {
+    load_files();
+    match session_key() {
+        Some(key) => println!("export {}={}", KEY_ENV, key.export()),
+        None => {
+            eprintln!("Your data is not encrypted.");
+            exit(1);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_sealed, open, seal, KdfParams, Key};
+
+    /// Cheap parameters so tests don't spend seconds in the key derivation.
+    const PARAMS: KdfParams = KdfParams {
+        memory_kib: 64,
+        iterations: 1,
+        lanes: 1,
+    };
+
+    #[test]
+    fn seals_and_opens_payloads() {
+        let key = Key::derive("correct horse", PARAMS, [7; 16]).unwrap();
+        let sealed = seal(&key, b"ingestions");
+        assert!(is_sealed(&sealed));
+        assert_eq!(open(&key, &sealed), Some(b"ingestions".to_vec()));
+        // Every save gets its own nonce
+        assert_ne!(seal(&key, b"ingestions"), sealed);
+
+        let same = Key::derive("correct horse", PARAMS, [7; 16]).unwrap();
+        assert_eq!(open(&same, &sealed), Some(b"ingestions".to_vec()));
+        let wrong = Key::derive("battery staple", PARAMS, [7; 16]).unwrap();
+        assert_eq!(open(&wrong, &sealed), None);
+        let salted = Key::derive("correct horse", PARAMS, [8; 16]).unwrap();
+        assert_eq!(open(&salted, &sealed), None);
+    }
+
+    #[test]
+    fn rejects_tampering() {
+        let key = Key::derive("correct horse", PARAMS, [7; 16]).unwrap();
+        let sealed = seal(&key, b"ingestions");
+        for i in [5, 40, sealed.len() - 1] {
+            let mut tampered = sealed.clone();
+            tampered[i] ^= 1;
+            assert_eq!(open(&key, &tampered), None, "byte {}", i);
+        }
+        assert_eq!(open(&key, &sealed[..20]), None);
+    }
+
+    #[test]
+    fn bounds_kdf_params() {
+        assert!(KdfParams::DEFAULT.is_reasonable());
+        assert!(PARAMS.is_reasonable());
+        let huge = KdfParams {
+            memory_kib: u32::MAX,
+            ..KdfParams::DEFAULT
+        };
+        assert!(!huge.is_reasonable());
+        let slow = KdfParams {
+            iterations: 1_000_000,
+            ..KdfParams::DEFAULT
+        };
+        assert!(!slow.is_reasonable());
+        let no_lanes = KdfParams {
+            lanes: 0,
+            ..KdfParams::DEFAULT
+        };
+        assert!(!no_lanes.is_reasonable());
+    }
+
+    #[test]
+    fn exports_keys() {
+        let key = Key::derive("correct horse", PARAMS, [7; 16]).unwrap();
+        let exported = key.export();
+        assert_eq!(exported.len(), 120);
+        assert!(Key::import(&exported) == Some(key));
+        assert!(Key::import(&exported[2..]).is_none());
+        assert!(Key::import(&"zz".repeat(60)).is_none());
+    }
+}
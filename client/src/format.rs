@@ -0,0 +1,188 @@
+//! Framing of the data files so damage is detected instead of decoded into garbage.
+//!
+//! A framed file starts with a header followed by the bincode payload:
+//!
+//! | Bytes | Content                              |
+//! |-------|--------------------------------------|
+//! | 4     | Magic `MEOW`                         |
+//! | 2     | Format version, little endian        |
+//! | 2     | Schema version, little endian        |
+//! | 8     | Payload length, little endian        |
+//! | 4     | CRC-32 of the payload, little endian |
+//!
+//! Version 1 frames are the same without the schema version.
+
+use crc::{Crc, CRC_32_ISO_HDLC};
+
+pub const MAGIC: &[u8; 4] = b"MEOW";
+/// Version of the frame layout written by this build.
+pub const VERSION: u16 = 2;
+const HEADER_LEN: usize = 20;
+const HEADER_LEN_V1: usize = 18;
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// Why a framed file could not be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameError {
+    Truncated,
+    UnsupportedVersion(u16),
+    LengthMismatch { expected: u64, actual: u64 },
+    ChecksumMismatch { expected: u32, actual: u32 },
+}
+
+impl std::fmt::Display for FrameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameError::Truncated => write!(f, "the header is truncated"),
+            FrameError::UnsupportedVersion(version) => write!(
+                f,
+                "format version {} is newer than this meowlog supports ({})",
+                version, VERSION
+            ),
+            FrameError::LengthMismatch { expected, actual } => write!(
+                f,
+                "expected {} bytes of data but found {}",
+                expected, actual
+            ),
+            FrameError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "checksum mismatch (expected {:08x}, got {:08x})",
+                expected, actual
+            ),
+        }
+    }
+}
+
+/// Verified contents of a framed file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame<'a> {
+    /// Schema version of the payload, `None` for version 1 frames
+    pub schema: Option<u16>,
+    pub payload: &'a [u8],
+}
+
+/// Wraps `payload` of version `schema` in a header with its length and checksum.
+pub fn encode(schema: u16, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&schema.to_le_bytes());
+    bytes.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&CRC.checksum(payload).to_le_bytes());
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+/// Returns the verified contents of a framed file, or `None` for files written before framing.
+///
+/// Unframed files are a bincode map, which starts with its length as a `u64`. A map large
+/// enough for that to begin with the magic can't exist, so the two never get mixed up.
+pub fn decode(bytes: &[u8]) -> Result<Option<Frame<'_>>, FrameError> {
+    if !bytes.starts_with(MAGIC) {
+        return Ok(None);
+    }
+    if bytes.len() < 6 {
+        return Err(FrameError::Truncated);
+    }
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version > VERSION {
+        return Err(FrameError::UnsupportedVersion(version));
+    }
+    let header_len = if version == 1 {
+        HEADER_LEN_V1
+    } else {
+        HEADER_LEN
+    };
+    if bytes.len() < header_len {
+        return Err(FrameError::Truncated);
+    }
+    let schema = (version != 1).then(|| u16::from_le_bytes([bytes[6], bytes[7]]));
+    let expected_len =
+        u64::from_le_bytes(bytes[header_len - 12..header_len - 4].try_into().unwrap());
+    let expected_crc = u32::from_le_bytes(bytes[header_len - 4..header_len].try_into().unwrap());
+    let payload = &bytes[header_len..];
+    if payload.len() as u64 != expected_len {
+        return Err(FrameError::LengthMismatch {
+            expected: expected_len,
+            actual: payload.len() as u64,
+        });
+    }
+    let actual_crc = CRC.checksum(payload);
+    if actual_crc != expected_crc {
+        return Err(FrameError::ChecksumMismatch {
+            expected: expected_crc,
+            actual: actual_crc,
+        });
+    }
+    Ok(Some(Frame { schema, payload }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode, Frame, FrameError, CRC, HEADER_LEN, HEADER_LEN_V1, MAGIC};
+
+    #[test]
+    fn round_trips_payloads() {
+        let framed = encode(2, b"meow");
+        assert_eq!(framed.len(), HEADER_LEN + 4);
+        assert_eq!(
+            decode(&framed),
+            Ok(Some(Frame {
+                schema: Some(2),
+                payload: b"meow"
+            }))
+        );
+        assert_eq!(
+            decode(&encode(0, &[])).unwrap().unwrap().payload,
+            &[] as &[u8]
+        );
+        // Files from before framing are passed through untouched
+        assert_eq!(decode(&[0; 8]), Ok(None));
+    }
+
+    #[test]
+    fn reads_version_1_frames() {
+        let mut framed = MAGIC.to_vec();
+        framed.extend_from_slice(&1_u16.to_le_bytes());
+        framed.extend_from_slice(&4_u64.to_le_bytes());
+        framed.extend_from_slice(&CRC.checksum(b"meow").to_le_bytes());
+        framed.extend_from_slice(b"meow");
+        assert_eq!(
+            decode(&framed),
+            Ok(Some(Frame {
+                schema: None,
+                payload: b"meow"
+            }))
+        );
+        framed[HEADER_LEN_V1] ^= 1;
+        assert!(matches!(
+            decode(&framed),
+            Err(FrameError::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn detects_damage() {
+        let framed = encode(2, b"some payload");
+
+        let mut flipped = framed.clone();
+        flipped[HEADER_LEN + 3] ^= 0x10;
+        assert!(matches!(
+            decode(&flipped),
+            Err(FrameError::ChecksumMismatch { .. })
+        ));
+
+        assert_eq!(
+            decode(&framed[..framed.len() - 2]),
+            Err(FrameError::LengthMismatch {
+                expected: 12,
+                actual: 10
+            })
+        );
+        assert_eq!(decode(&framed[..10]), Err(FrameError::Truncated));
+
+        let mut newer = framed.clone();
+        newer[4] = 0xff;
+        assert_eq!(decode(&newer), Err(FrameError::UnsupportedVersion(0xff)));
+    }
+}
@@ -0,0 +1,307 @@
+use std::process::exit;
+
+use crate::drugs_parser::{Combo, ComboGroup, Drug, Duration, DRUGS};
+use crate::tolerance::print_tolerance;
+use crate::util::wrap_text;
+
+const WIDTH: usize = 80;
+
+pub fn substance_info(name: &str) -> Result<(), std::io::Error> {
+    let drug = match DRUGS.get(name) {
+        Some(drug) => drug,
+        None => {
+            eprintln!("'{}' is not in the drug database!", name);
+            exit(1);
+        }
+    };
+
+    print_header(drug);
+    print_dosage(drug);
+    print_timings(drug);
+    print_warnings(drug);
+    print_tolerance(drug);
+    print_links(drug);
+
+    Ok(())
+}
+
+fn print_header(drug: &Drug) {
+    println!("{}", drug.pretty_name);
+    println!("{}", "=".repeat(drug.pretty_name.chars().count()));
+    if let Some(aliases) = drug.aliases.as_ref().filter(|a| !a.is_empty()) {
+        println!("Aliases:    {}", aliases.join(", "));
+    }
+    if let Some(categories) = drug.categories.as_ref().filter(|c| !c.is_empty()) {
+        let categories = categories.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        println!("Categories: {}", categories.join(", "));
+    }
+    if let Some(summary) = &drug.properties.summary {
+        println!("\n{}", wrap_text(summary, WIDTH, ""));
+    }
+}
+
+fn print_dosage(drug: &Drug) {
+    let routes = match &drug.formatted_dose {
+        Some(dose) => dose.0.iter().collect(),
+        None => Vec::new(),
+    };
+    if routes.is_empty() && drug.properties.dose.is_none() {
+        return;
+    }
+    println!("\nDosage");
+    println!("------");
+
+    if routes.is_empty() {
+        // No structured data, fall back to the free-form text
+        if let Some(dose) = &drug.properties.dose {
+            println!("{}", wrap_text(dose, WIDTH, "  "));
+        }
+    } else {
+        // Only show tiers that at least one route has data for
+        let columns = (0..7)
+            .filter(|&i| {
+                routes
+                    .iter()
+                    .any(|(_, dosage)| dosage.tiers()[i].1.is_some())
+            })
+            .collect::<Vec<_>>();
+        let mut rows: Vec<Vec<String>> = vec![std::iter::once("Route")
+            .map(str::to_string)
+            .chain(
+                columns
+                    .iter()
+                    .map(|&i| routes[0].1.tiers()[i].0.to_string()),
+            )
+            .collect()];
+        for (route, dosage) in &routes {
+            let tiers = dosage.tiers();
+            rows.push(
+                std::iter::once(route.to_string())
+                    .chain(
+                        columns
+                            .iter()
+                            .map(|&i| tiers[i].1.cloned().unwrap_or_else(|| "-".to_string())),
+                    )
+                    .collect(),
+            );
+        }
+        print_table(&rows);
+    }
+
+    if let Some(note) = &drug.dose_note {
+        println!("{}", wrap_text(note.trim(), WIDTH, "  "));
+    }
+}
+
+fn print_timings(drug: &Drug) {
+    let timings = [
+        ("Onset", &drug.formatted_onset),
+        ("Duration", &drug.formatted_duration),
+        ("After-effects", &drug.formatted_aftereffects),
+    ];
+    if timings.iter().all(|(_, t)| t.is_none()) && drug.properties.half_life.is_none() {
+        return;
+    }
+    println!("\nTimings");
+    println!("-------");
+    let mut lines: Vec<(String, String)> = Vec::new();
+    for (label, timing) in timings {
+        if let Some(timing) = timing {
+            for (route, value) in timing.entries() {
+                let route = match route {
+                    Some(route) => format!(" ({})", route),
+                    None => String::new(),
+                };
+                lines.push((format!("{}{}:", label, route), format_timing(value, timing)));
+            }
+        }
+    }
+    if let Some(half_life) = &drug.properties.half_life {
+        lines.push(("Half-life:".to_string(), half_life.clone()));
+    }
+    let width = lines
+        .iter()
+        .map(|(l, _)| l.chars().count())
+        .max()
+        .unwrap_or(0);
+    for (label, value) in lines {
+        println!("  {:<width$} {}", label, value, width = width);
+    }
+}
+
+fn format_timing(value: &str, timing: &Duration) -> String {
+    match &timing.unit {
+        Some(unit) => format!("{} {}", value, unit),
+        None => value.to_string(),
+    }
+}
+
+fn print_warnings(drug: &Drug) {
+    let warnings = [
+        ("Avoid", &drug.properties.avoid),
+        ("Warning", &drug.properties.warning),
+        ("Contraindications", &drug.properties.contraindications),
+        ("Risks", &drug.properties.risks),
+        ("Overdose symptoms", &drug.properties.overdose_symptoms),
+        ("Tolerance", &drug.properties.tolerance),
+    ];
+    if warnings.iter().all(|(_, w)| w.is_none()) {
+        return;
+    }
+    println!("\nWarnings");
+    println!("--------");
+    for (label, warning) in warnings {
+        if let Some(warning) = warning {
+            println!("  {}:", label);
+            println!("{}", wrap_text(warning, WIDTH, "    "));
+        }
+    }
+}
+
+fn print_links(drug: &Drug) {
+    let mut links: Vec<(String, String)> = Vec::new();
+    if let Some(drug_links) = &drug.links {
+        links.push(("Experiences".to_string(), drug_links.experiences.clone()));
+        if let Some(pihkal) = &drug_links.pihkal {
+            links.push(("PiHKAL".to_string(), pihkal.clone()));
+        }
+        if let Some(tihkal) = &drug_links.tihkal {
+            links.push(("TiHKAL".to_string(), tihkal.clone()));
+        }
+    }
+    if let Some(wiki) = &drug.properties.wiki {
+        links.push(("Wiki".to_string(), wiki.clone()));
+    }
+    if let Some(pweffects) = &drug.pweffects {
+        let mut effects = pweffects.iter().collect::<Vec<_>>();
+        effects.sort();
+        links.extend(effects.into_iter().map(|(e, url)| (e.clone(), url.clone())));
+    }
+    if links.is_empty() {
+        return;
+    }
+    println!("\nReferences");
+    println!("----------");
+    let width = links
+        .iter()
+        .map(|(l, _)| l.chars().count())
+        .max()
+        .unwrap_or(0);
+    for (label, url) in links {
+        println!("  {:<width$}  {}", label, url, width = width);
+    }
+}
+
+fn print_table(rows: &[Vec<String>]) {
+    let widths = (0..rows[0].len())
+        .map(|i| rows.iter().map(|r| r[i].chars().count()).max().unwrap_or(0))
+        .collect::<Vec<_>>();
+    for row in rows {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("  {}", line.trim_end());
+    }
+}
+
+/// A `check-combo` argument, either a concrete drug or a combo group like `opioids`.
+struct ComboSubject {
+    name: String,
+    groups: Vec<ComboGroup>,
+}
+
+fn resolve_combo_subject(name: &str) -> Option<ComboSubject> {
+    if let Some(drug) = DRUGS.get(name) {
+        return Some(ComboSubject {
+            name: drug.pretty_name.clone(),
+            groups: drug.lookup_groups(),
+        });
+    }
+    let lowercase = name.trim().to_lowercase();
+    ComboGroup::KNOWN
+        .into_iter()
+        .find(|g| g.as_str() == lowercase)
+        .map(|group| ComboSubject {
+            name: group.to_string(),
+            groups: vec![group],
+        })
+}
+
+pub fn check_combo(names: &[String]) -> Result<(), std::io::Error> {
+    let mut subjects = Vec::new();
+    for name in names {
+        match resolve_combo_subject(name) {
+            Some(subject) => subjects.push(subject),
+            None => {
+                eprintln!(
+                    "'{}' is neither in the drug database nor a combo group!",
+                    name
+                );
+                exit(1);
+            }
+        }
+    }
+
+    let combos: Vec<Vec<Option<&Combo>>> = subjects
+        .iter()
+        .map(|a| {
+            subjects
+                .iter()
+                .map(|b| DRUGS.group_combo(&a.groups, &b.groups))
+                .collect()
+        })
+        .collect();
+    let status = |combo: Option<&Combo>| match combo {
+        Some(combo) => combo.status.to_string(),
+        None => "No data".to_string(),
+    };
+
+    let mut rows: Vec<Vec<String>> = vec![std::iter::once(String::new())
+        .chain(subjects.iter().map(|s| s.name.clone()))
+        .collect()];
+    for (i, subject) in subjects.iter().enumerate() {
+        rows.push(
+            std::iter::once(subject.name.clone())
+                .chain((0..subjects.len()).map(|j| {
+                    if i == j {
+                        "-".to_string()
+                    } else {
+                        status(combos[i][j])
+                    }
+                }))
+                .collect(),
+        );
+    }
+    print_table(&rows);
+
+    let pairs = (0..subjects.len()).flat_map(|i| (i + 1..subjects.len()).map(move |j| (i, j)));
+    for (i, j) in pairs {
+        println!(
+            "\n{} + {}: {}",
+            subjects[i].name,
+            subjects[j].name,
+            status(combos[i][j])
+        );
+        let combo = match combos[i][j] {
+            Some(combo) => combo,
+            None => continue,
+        };
+        if let Some(note) = &combo.note {
+            println!("{}", wrap_text(note, WIDTH, "  "));
+        }
+        for source in combo.sources.iter().flatten() {
+            let citation = [&source.author, &source.title, &source.url]
+                .into_iter()
+                .flatten()
+                .map(|s| s.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            println!("  - {}", citation);
+        }
+    }
+
+    Ok(())
+}
@@ -1,18 +1,26 @@
 use crate::ingestions_util::{
-    ensure_ingestion_files, get_dose_unit, get_ingestion_confirmation, get_ingestion_method,
+    check_dose_tier, check_dose_unit, check_interactions, ensure_ingestion_files, find_substance,
+    format_dose, format_dose_tier, get_dose_unit, get_ingestion_confirmation, get_ingestion_method,
     get_substance, get_user_date, get_user_time,
 };
-use chrono::{NaiveDate, NaiveTime, Utc};
+use crate::util::ensure_interactive;
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, Utc};
 use inquire;
 use serde::{self, Deserialize, Serialize};
 use std::cmp::PartialEq;
 use std::collections::HashMap;
 use std::fmt::Formatter;
 use std::process::exit;
+use std::str::FromStr;
 use uuid::Uuid;
 
+use crate::benzo;
+use crate::drugs_parser::Route;
+use crate::encryption;
+use crate::store::{FileStore, Store};
 use crate::substances::Substance;
-use crate::INGESTIONS_FILE;
+use crate::tolerance;
+use crate::units::{self, DoseUnit};
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Ingestion {
@@ -27,37 +35,82 @@ impl std::fmt::Display for Ingestion {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "{} {}   {} {}{}",
+            "{} {}   {} {}",
             self.date,
             self.time.format("%H:%M"),
             self.substance.name,
-            self.dose.value,
-            self.dose.unit
+            self.dose
         )
     }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, PartialOrd)]
 pub struct Dose {
-    pub unit: String,
+    pub unit: DoseUnit,
     pub value: f64,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, strum::Display, strum::EnumIter)]
-#[strum(serialize_all = "snake_case")]
-pub enum DoseUnit {
-    Ug,
-    Mg,
-    G,
-    Ml,
+impl Dose {
+    /// The same dose in another unit, `None` if the substance lacks a concentration or unit
+    /// definition needed for the conversion.
+    pub fn convert(&self, unit: DoseUnit, substance: &Substance) -> Option<Dose> {
+        Some(Dose {
+            value: units::convert(self.value, &self.unit, &unit, substance)?,
+            unit,
+        })
+    }
+
+    pub fn milligrams(&self, substance: &Substance) -> Option<f64> {
+        Some(self.convert(DoseUnit::Mg, substance)?.value)
+    }
+}
+
+impl std::fmt::Display for Dose {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.unit {
+            DoseUnit::Custom(unit) => write!(f, "{} {}", self.value, unit),
+            unit => write!(f, "{}{}", self.value, unit),
+        }
+    }
+}
+
+impl FromStr for Dose {
+    type Err = String;
+
+    /// Parses doses like `20mg`, `0.5 ml`, `150ug` or `2 tabs`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let split = s
+            .find(|c: char| c.is_alphabetic() || c == 'µ')
+            .ok_or_else(|| format!("Dose `{}` is missing a unit", s))?;
+        let (value, unit) = s.split_at(split);
+        let value: f64 = value
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid dose amount `{}`", value.trim()))?;
+        // Anything that is not a built-in unit is looked up in the substance's custom units
+        let unit = DoseUnit::from_str(unit.trim()).map_err(|e| e.to_string())?;
+        Ok(Dose { unit, value })
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Eq, strum::Display, strum::EnumIter, PartialEq)]
+#[derive(
+    Serialize,
+    Deserialize,
+    Debug,
+    Clone,
+    Eq,
+    strum::Display,
+    strum::EnumIter,
+    strum::EnumString,
+    PartialEq,
+)]
+#[strum(ascii_case_insensitive)]
 pub enum IngestionMethod {
     Oral,
     Sublingual,
     Buccal,
-    Insuffulated,
+    Insufflated,
     Rectal,
     Transdermal,
     Subcutaneous,
@@ -65,24 +118,122 @@ pub enum IngestionMethod {
     Intravenous,
     Smoked,
     Inhaled,
+    // New variants go last, ingestions are stored by variant index
+    #[strum(to_string = "Oral (IR)", serialize = "oral-ir")]
+    OralIr,
+    #[strum(to_string = "Oral (ER)", serialize = "oral-er")]
+    OralEr,
+    #[strum(to_string = "Insufflated (IR)", serialize = "insufflated-ir")]
+    InsufflatedIr,
+    #[strum(to_string = "Insufflated (XR)", serialize = "insufflated-xr")]
+    InsufflatedXr,
 }
 
-pub fn add_ingestion() {
-    let mut ingesstions_bytes_loaded_des: HashMap<Uuid, Ingestion> = ensure_ingestion_files();
+impl IngestionMethod {
+    /// Drug database routes for this method, most specific first.
+    ///
+    /// Release variants fall back to the plain route and vice versa, since most substances only
+    /// have data for one of them.
+    pub fn routes(&self) -> Vec<Route> {
+        match self {
+            IngestionMethod::Oral => vec![Route::Oral, Route::OralIr],
+            IngestionMethod::OralIr => vec![Route::OralIr, Route::Oral],
+            IngestionMethod::OralEr => vec![Route::OralEr, Route::Oral],
+            IngestionMethod::Sublingual => vec![Route::Sublingual],
+            IngestionMethod::Buccal => vec![Route::Buccal],
+            IngestionMethod::Insufflated => vec![Route::Insufflated, Route::InsufflatedIr],
+            IngestionMethod::InsufflatedIr => vec![Route::InsufflatedIr, Route::Insufflated],
+            IngestionMethod::InsufflatedXr => vec![Route::InsufflatedXr, Route::Insufflated],
+            IngestionMethod::Rectal => vec![Route::Rectal],
+            IngestionMethod::Transdermal => vec![Route::Transdermal],
+            IngestionMethod::Subcutaneous => vec![],
+            IngestionMethod::Intramuscular => vec![Route::Intramuscular],
+            IngestionMethod::Intravenous => vec![Route::Intravenous],
+            IngestionMethod::Smoked => vec![Route::Smoked],
+            IngestionMethod::Inhaled => vec![Route::Vapourized],
+        }
+    }
 
-    let substance = get_substance();
+    pub fn from_route(route: &Route) -> Option<IngestionMethod> {
+        match route {
+            Route::Oral => Some(IngestionMethod::Oral),
+            Route::OralIr => Some(IngestionMethod::OralIr),
+            Route::OralEr => Some(IngestionMethod::OralEr),
+            Route::OralMaoi => Some(IngestionMethod::Oral),
+            Route::Sublingual => Some(IngestionMethod::Sublingual),
+            Route::Buccal => Some(IngestionMethod::Buccal),
+            Route::Insufflated => Some(IngestionMethod::Insufflated),
+            Route::InsufflatedIr => Some(IngestionMethod::InsufflatedIr),
+            Route::InsufflatedXr => Some(IngestionMethod::InsufflatedXr),
+            Route::Rectal => Some(IngestionMethod::Rectal),
+            Route::Transdermal => Some(IngestionMethod::Transdermal),
+            Route::Intramuscular => Some(IngestionMethod::Intramuscular),
+            Route::Intravenous => Some(IngestionMethod::Intravenous),
+            Route::Smoked => Some(IngestionMethod::Smoked),
+            Route::Vapourized => Some(IngestionMethod::Inhaled),
+            Route::Other(_) => None,
+        }
+    }
+}
 
-    let ingestion_method = get_ingestion_method();
+pub fn add_ingestion(
+    substance_name: Option<String>,
+    dose_arg: Option<Dose>,
+    method_arg: Option<IngestionMethod>,
+    at: Option<NaiveDateTime>,
+    yes: bool,
+) {
+    let ingesstions_bytes_loaded_des: HashMap<Uuid, Ingestion> = ensure_ingestion_files();
+    let prompted =
+        substance_name.is_none() || dose_arg.is_none() || method_arg.is_none() || at.is_none();
 
-    let current_datetime = Utc::now().naive_utc();
-    let date: NaiveDate = get_user_date(current_datetime);
-    let time: NaiveTime = get_user_time(current_datetime);
-    let dose_num: f64 = inquire::prompt_f64("Enter the amount consumed:").unwrap();
-    let dose_unit: DoseUnit = get_dose_unit();
+    let substance = match &substance_name {
+        Some(name) => match find_substance(name) {
+            Some(substance) => substance,
+            None => {
+                eprintln!("Substance '{}' does not exist!", name);
+                exit(1);
+            }
+        },
+        None => {
+            ensure_interactive("--substance");
+            get_substance()
+        }
+    };
+
+    let ingestion_method = match &method_arg {
+        Some(ingestion_method) => ingestion_method.clone(),
+        None => {
+            ensure_interactive("--method");
+            get_ingestion_method(&substance)
+        }
+    };
+
+    let (date, time) = match at {
+        Some(at) => (at.date(), at.time()),
+        None => {
+            ensure_interactive("--at");
+            let current_datetime = Utc::now().naive_utc();
+            let date: NaiveDate = get_user_date(current_datetime);
+            let time: NaiveTime = get_user_time(current_datetime);
+            (date, time)
+        }
+    };
 
-    let dose = Dose {
-        unit: dose_unit.to_string(),
-        value: dose_num,
+    let dose = match &dose_arg {
+        Some(dose) => {
+            check_dose_unit(dose, &substance);
+            dose.clone()
+        }
+        None => {
+            ensure_interactive("--dose");
+            let dose_num: f64 = inquire::prompt_f64("Enter the amount consumed:").unwrap();
+            let dose_unit: DoseUnit = get_dose_unit(&substance);
+            Dose {
+                unit: dose_unit,
+                value: dose_num,
+            }
+        }
     };
 
     let ingestion = Ingestion {
@@ -93,30 +244,38 @@ pub fn add_ingestion() {
         time,
     };
 
-    let confirm = get_ingestion_confirmation(ingestion.clone());
+    // Fully specified ingestions come from scripts, so there is nobody to confirm them
+    let confirm = !prompted || get_ingestion_confirmation(ingestion.clone());
     if confirm {
-        ingesstions_bytes_loaded_des.insert(Uuid::new_v4(), ingestion.clone());
-        let ingestion_ser = bincode::serialize(&ingesstions_bytes_loaded_des).unwrap();
-        std::fs::write(INGESTIONS_FILE.to_string(), ingestion_ser).unwrap();
+        if !check_dose_tier(&ingestion, yes)
+            || !check_interactions(&ingestion, &ingesstions_bytes_loaded_des, yes)
+        {
+            println!("Ingestion was not logged.");
+            return;
+        }
+        tolerance::warn_tolerance(&ingestion, &ingesstions_bytes_loaded_des);
+        FileStore::ingestions().insert(ingestion).unwrap();
     } else {
-        add_ingestion();
+        add_ingestion(substance_name, dose_arg, method_arg, at, yes);
     }
 }
 
 pub fn list_ingestions() -> Result<(), std::io::Error> {
-    let ing_read = std::fs::read(INGESTIONS_FILE.to_string()).unwrap();
-    let ing_des: HashMap<Uuid, Ingestion> = bincode::deserialize(&ing_read).unwrap();
+    let ing_des = ensure_ingestion_files();
     for (id, ingestion) in ing_des.clone().into_iter() {
         println!(
-            "Substance:  {} ({})\nDose:       {} {}\nTime:       {}\nUUID:       {:?}\n",
+            "Substance:  {} ({})\nDose:       {}{}\nTime:       {}\nUUID:       {:?}\n",
             ingestion.substance.name,
             ingestion.ingestion_method,
-            ingestion.dose.value,
-            ingestion.dose.unit,
+            format_dose(&ingestion),
+            format_dose_tier(&ingestion),
             ingestion.time,
             id
         );
     }
+    if let Some(summary) = benzo::summary(&ing_des, benzo::DEFAULT_WINDOW_HOURS) {
+        println!("{}", summary);
+    }
 
     Ok(())
 }
@@ -140,20 +299,16 @@ pub fn edit_ingestion() -> Result<(), std::io::Error> {
         inquire::Select::new("Which ingestion do you want to edit?", ingest_sel_vec_ing)
             .prompt()
             .unwrap();
-    let ing_id = ing_des
-        .iter()
-        .map(|(key, &ref val)| {
-            if val.substance.name == ingest_select.substance.name
+    let ing_id = FileStore::ingestions()
+        .query(|val| {
+            val.substance.name == ingest_select.substance.name
                 && val.substance.substance_class == ingest_select.substance.substance_class
                 && val.date == ingest_select.date
                 && val.time == ingest_select.time
-            {
-                key.clone()
-            } else {
-                unsafe { std::hint::unreachable_unchecked() }
-            }
-        })
-        .collect::<Vec<Uuid>>();
+        })?
+        .first()
+        .map(|(id, _)| *id)
+        .expect("Fatal error. Couldn't find the selected ingestion.");
 
     let edit_select = inquire::MultiSelect::new(
         "What do you want to edit?",
@@ -175,19 +330,16 @@ pub fn edit_ingestion() -> Result<(), std::io::Error> {
                 };
                 let confirm = get_ingestion_confirmation(ingestion.clone());
                 if confirm {
-                    let mut ing_des = ensure_ingestion_files();
-                    ing_des.insert(ing_id[0], ingestion.clone());
-                    let ingestion_ser = bincode::serialize(&ing_des).unwrap();
-                    std::fs::write(INGESTIONS_FILE.to_string(), ingestion_ser).unwrap();
+                    FileStore::ingestions().update(ing_id, ingestion.clone())?;
                 } else {
                     edit_ingestion();
                 }
             }
             "Dose" => {
                 let dose_num: f64 = inquire::prompt_f64("Enter the amount consumed:").unwrap();
-                let dose_unit: DoseUnit = get_dose_unit();
+                let dose_unit: DoseUnit = get_dose_unit(&ingest_select.substance);
                 let dose = Dose {
-                    unit: dose_unit.to_string(),
+                    unit: dose_unit,
                     value: dose_num,
                 };
                 let ingestion = Ingestion {
@@ -199,16 +351,13 @@ pub fn edit_ingestion() -> Result<(), std::io::Error> {
                 };
                 let confirm = get_ingestion_confirmation(ingestion.clone());
                 if confirm {
-                    let mut ing_des = ensure_ingestion_files();
-                    ing_des.insert(ing_id[0], ingestion.clone());
-                    let ingestion_ser = bincode::serialize(&ing_des).unwrap();
-                    std::fs::write(INGESTIONS_FILE.to_string(), ingestion_ser).unwrap();
+                    FileStore::ingestions().update(ing_id, ingestion.clone())?;
                 } else {
                     edit_ingestion();
                 }
             }
             "Ingestion Method" => {
-                let ingestion_method = get_ingestion_method();
+                let ingestion_method = get_ingestion_method(&ingest_select.substance);
                 let ingestion = Ingestion {
                     substance: ingest_select.substance.clone(),
                     dose: ingest_select.dose.clone(),
@@ -218,10 +367,7 @@ pub fn edit_ingestion() -> Result<(), std::io::Error> {
                 };
                 let confirm = get_ingestion_confirmation(ingestion.clone());
                 if confirm {
-                    let mut ing_des = ensure_ingestion_files();
-                    ing_des.insert(ing_id[0], ingestion.clone());
-                    let ingestion_ser = bincode::serialize(&ing_des).unwrap();
-                    std::fs::write(INGESTIONS_FILE.to_string(), ingestion_ser).unwrap();
+                    FileStore::ingestions().update(ing_id, ingestion.clone())?;
                 } else {
                     edit_ingestion();
                 }
@@ -237,10 +383,7 @@ pub fn edit_ingestion() -> Result<(), std::io::Error> {
                 };
                 let confirm = get_ingestion_confirmation(ingestion.clone());
                 if confirm {
-                    let mut ing_des = ensure_ingestion_files();
-                    ing_des.insert(ing_id[0], ingestion.clone());
-                    let ingestion_ser = bincode::serialize(&ing_des).unwrap();
-                    std::fs::write(INGESTIONS_FILE.to_string(), ingestion_ser).unwrap();
+                    FileStore::ingestions().update(ing_id, ingestion.clone())?;
                 } else {
                     edit_ingestion();
                 }
@@ -256,10 +399,7 @@ pub fn edit_ingestion() -> Result<(), std::io::Error> {
                 };
                 let confirm = get_ingestion_confirmation(ingestion.clone());
                 if confirm {
-                    let mut ing_des = ensure_ingestion_files();
-                    ing_des.insert(ing_id[0], ingestion.clone());
-                    let ingestion_ser = bincode::serialize(&ing_des).unwrap();
-                    std::fs::write(INGESTIONS_FILE.to_string(), ingestion_ser).unwrap();
+                    FileStore::ingestions().update(ing_id, ingestion.clone())?;
                 } else {
                     edit_ingestion();
                 }
@@ -270,8 +410,49 @@ pub fn edit_ingestion() -> Result<(), std::io::Error> {
     Ok(())
 }
 
+pub fn remove_ingestion(id: Option<Uuid>) -> Result<(), std::io::Error> {
+    let mut store = FileStore::ingestions();
+    let ing_des = ensure_ingestion_files();
+    if ing_des.is_empty() {
+        eprintln!("No ingestions to remove!");
+        exit(1);
+    }
+
+    match id {
+        Some(id) => {
+            if store.delete(&id)?.is_none() {
+                eprintln!("No ingestion with UUID {} found!", id);
+                exit(1);
+            }
+        }
+        None => {
+            let mut ingest_sel: Vec<(Uuid, Ingestion)> = ing_des.into_iter().collect();
+            ingest_sel.sort_by_key(|(_, ingestion)| (ingestion.date, ingestion.time));
+            let (ingest_sel_vec_id, ingest_sel_vec_ing): (Vec<Uuid>, Vec<Ingestion>) =
+                ingest_sel.into_iter().unzip();
+
+            let ingest_select = inquire::MultiSelect::new(
+                "Which ingestion do you want to remove?",
+                ingest_sel_vec_ing,
+            )
+            .raw_prompt()
+            .unwrap();
+            for option in ingest_select {
+                let confirm = inquire::prompt_confirmation(format!(
+                    "Are you sure you want to remove '{}'? [y/N]",
+                    option.value
+                ))
+                .unwrap();
+                if confirm {
+                    store.delete(&ingest_sel_vec_id[option.index])?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
 pub fn create_ingestions_file() -> Result<(), std::io::Error> {
-    let hash: HashMap<Uuid, Ingestion> = HashMap::new();
-    let hash_ser = bincode::serialize(&hash).unwrap();
-    std::fs::write(INGESTIONS_FILE.to_string(), hash_ser)
+    encryption::unlock_for_new_file();
+    FileStore::ingestions().save(&HashMap::new())
 }
@@ -1,27 +1,39 @@
-use crate::ingestions::{DoseUnit, Ingestion, IngestionMethod};
+use crate::drugs_parser::{DoseTier, DRUGS};
+use crate::ingestions::{Dose, Ingestion, IngestionMethod};
+use crate::store::{load_or_exit, FileStore};
 use crate::substances::Substance;
-use crate::util::path_exists;
-use crate::INGESTIONS_FILE;
+use crate::units::{Dimension, DoseUnit};
 use chrono::NaiveDateTime;
 use inquire;
 use std::{collections::HashMap, process::exit};
 use strum::IntoEnumIterator;
 use uuid::Uuid;
 
+/// Loads every ingestion with the current version of its substance, see
+/// `with_current_substances`.
 pub fn ensure_ingestion_files() -> HashMap<Uuid, Ingestion> {
-    let ingestions_bytes_loaded_des: HashMap<Uuid, Ingestion>;
-    if path_exists(INGESTIONS_FILE.to_string()) {
-        let substances_bytes_loaded =
-            std::fs::read(INGESTIONS_FILE.to_string()).expect("Could not read ingestions file");
-        ingestions_bytes_loaded_des = bincode::deserialize(&substances_bytes_loaded).expect("Could not deserialize ingestions file. If you are tech-savvy try fixing it with a hex editor.");
-    } else {
-        std::fs::File::create(INGESTIONS_FILE.to_string()).unwrap();
-        ingestions_bytes_loaded_des = HashMap::new();
-        let ingesstions_bytes_loaded_ser =
-            bincode::serialize(&ingestions_bytes_loaded_des).unwrap();
-        std::fs::write(INGESTIONS_FILE.to_string(), ingesstions_bytes_loaded_ser).unwrap();
+    with_current_substances(
+        load_or_exit(&FileStore::ingestions()),
+        &load_or_exit(&FileStore::substances()),
+    )
+}
+
+/// Replaces the copy of the substance stored with every ingestion by the substance as it is now,
+/// so a concentration or unit added later also converts doses logged before it. Ingestions of
+/// removed substances keep their copy.
+pub fn with_current_substances(
+    mut ingestions: HashMap<Uuid, Ingestion>,
+    substances: &HashMap<Uuid, Substance>,
+) -> HashMap<Uuid, Ingestion> {
+    for ingestion in ingestions.values_mut() {
+        if let Some(current) = substances
+            .values()
+            .find(|substance| substance.name == ingestion.substance.name)
+        {
+            ingestion.substance = current.clone();
+        }
     }
-    ingestions_bytes_loaded_des
+    ingestions
 }
 
 pub fn get_user_date(current: NaiveDateTime) -> chrono::NaiveDate {
@@ -54,13 +66,17 @@ pub fn get_user_time(current: NaiveDateTime) -> chrono::NaiveTime {
     time
 }
 
-pub fn get_dose_unit() -> DoseUnit {
-    let dose_unit = inquire::Select::new(
-        "What unit should be used?",
-        DoseUnit::iter().collect::<Vec<_>>(),
-    )
-    .prompt()
-    .unwrap();
+pub fn get_dose_unit(substance: &Substance) -> DoseUnit {
+    let mut units = DoseUnit::builtin();
+    units.extend(
+        substance
+            .units
+            .iter()
+            .map(|unit| DoseUnit::Custom(unit.name.clone())),
+    );
+    let dose_unit = inquire::Select::new("What unit should be used?", units)
+        .prompt()
+        .unwrap();
     dose_unit
 }
 
@@ -91,20 +107,48 @@ pub fn get_substance() -> Substance {
         exit(1);
     }
 
-    let substance = substances.into_iter().next().unwrap();
-    dbg!(&substance);
+    substances.into_iter().next().unwrap()
+}
+
+pub fn find_substance(name: &str) -> Option<Substance> {
+    crate::substance_util::ensure_substance_file()
+        .into_values()
+        .find(|s| s.name == name)
+}
 
-    substance
+/// Parses `YYYY-MM-DD HH:MM`, or `HH:MM` for today.
+pub fn parse_ingestion_datetime(input: &str) -> Result<NaiveDateTime, String> {
+    let input = input.trim();
+    if let Ok(datetime) = NaiveDateTime::parse_from_str(input, "%Y-%m-%d %H:%M") {
+        return Ok(datetime);
+    }
+    match chrono::NaiveTime::parse_from_str(input, "%H:%M") {
+        Ok(time) => Ok(chrono::Utc::now().naive_utc().date().and_time(time)),
+        Err(_) => Err(format!(
+            "Invalid time `{}`, use the format YYYY-MM-DD HH:MM or HH:MM",
+            input
+        )),
+    }
 }
 
-pub fn get_ingestion_method() -> IngestionMethod {
-    let ingestion_method = inquire::Select::new(
-        "How did you ingest?",
-        IngestionMethod::iter().collect::<Vec<_>>(),
-    )
-    .prompt()
-    .unwrap();
-    ingestion_method
+pub fn get_ingestion_method(substance: &Substance) -> IngestionMethod {
+    // Offer the routes the drug database has doses for first
+    let documented = DRUGS
+        .get(&substance.name)
+        .and_then(|drug| drug.formatted_dose.as_ref())
+        .map(|dose| {
+            dose.0
+                .keys()
+                .filter_map(IngestionMethod::from_route)
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let mut methods = IngestionMethod::iter().collect::<Vec<_>>();
+    methods.sort_by_key(|method| !documented.contains(method));
+
+    inquire::Select::new("How did you ingest?", methods)
+        .prompt()
+        .unwrap()
 }
 
 pub fn get_ingestion_confirmation(ingestion: Ingestion) -> bool {
@@ -112,11 +156,237 @@ pub fn get_ingestion_confirmation(ingestion: Ingestion) -> bool {
         "Substance:  {} ({})\nDose:       {}{}\nTime:       {}\n",
         ingestion.substance.name,
         ingestion.ingestion_method,
-        ingestion.dose.value,
-        ingestion.dose.unit,
+        format_dose(&ingestion),
+        format_dose_tier(&ingestion),
         ingestion.time,
     );
     let confirm =
         inquire::prompt_confirmation("Does the ingestion above look alright? [y/N]").unwrap();
     confirm
 }
+
+/// Hours an ingestion counts as active when the drug database has no timings for it.
+pub const DEFAULT_ACTIVE_HOURS: f64 = 24.0;
+
+pub fn ingestion_datetime(ingestion: &Ingestion) -> NaiveDateTime {
+    ingestion.date.and_time(ingestion.time)
+}
+
+/// Matches substances by name or by resolving to the same drug, so aliases count as well.
+pub fn is_same_substance(a: &str, b: &str) -> bool {
+    if a.eq_ignore_ascii_case(b) {
+        return true;
+    }
+    match (DRUGS.get(a), DRUGS.get(b)) {
+        (Some(a), Some(b)) => a.name == b.name,
+        _ => false,
+    }
+}
+
+/// Warns about interactions between `ingestion` and ingestions that are still active.
+///
+/// Returns `false` if the user declined to log a dangerous or unsafe combination.
+pub fn check_interactions(
+    ingestion: &Ingestion,
+    ingestions: &HashMap<Uuid, Ingestion>,
+    yes: bool,
+) -> bool {
+    let drug = match DRUGS.get(&ingestion.substance.name) {
+        Some(drug) => drug,
+        None => return true,
+    };
+    let taken_at = ingestion_datetime(ingestion);
+
+    let mut active = ingestions
+        .values()
+        .filter_map(|other| {
+            let other_drug = DRUGS.get(&other.substance.name)?;
+            let hours = other_drug
+                .active_hours(&other.ingestion_method.routes())
+                .unwrap_or(DEFAULT_ACTIVE_HOURS);
+            let other_at = ingestion_datetime(other);
+            let active_until = other_at + chrono::Duration::minutes((hours * 60.0) as i64);
+            (other_at <= taken_at && taken_at < active_until && other_drug.name != drug.name)
+                .then_some((other, other_drug))
+        })
+        .collect::<Vec<_>>();
+    active.sort_by_key(|(other, _)| ingestion_datetime(other));
+
+    let mut dangerous = false;
+    for (other, other_drug) in active {
+        if let Some(combo) = DRUGS.combo(drug, other_drug) {
+            println!(
+                "Interaction with {} taken at {}: {}",
+                other_drug.pretty_name,
+                ingestion_datetime(other).format("%Y-%m-%d %H:%M"),
+                combo.status
+            );
+            if let Some(note) = &combo.note {
+                println!("{}", crate::util::wrap_text(note, 80, "    "));
+            }
+            dangerous |= combo.status.is_dangerous();
+        }
+    }
+
+    if !dangerous || yes {
+        return true;
+    }
+    crate::util::ensure_interactive("--yes for a dangerous combination");
+    inquire::prompt_confirmation(
+        "This combination is dangerous. Do you really want to log this ingestion? [y/N]",
+    )
+    .unwrap()
+}
+
+/// Classifies the dose against the reference ranges for the ingestion's route.
+pub fn dose_tier(ingestion: &Ingestion) -> Option<DoseTier> {
+    let drug = DRUGS.get(&ingestion.substance.name)?;
+    let doses = &drug.formatted_dose.as_ref()?.0;
+    let dosage = ingestion
+        .ingestion_method
+        .routes()
+        .iter()
+        .find_map(|route| doses.get(route))?;
+    // Reference doses are usually masses, so logged volumes are converted first
+    let unit = dosage.ranges().first()?.1.unit;
+    let dose = ingestion
+        .dose
+        .convert(DoseUnit::from_range_unit(unit)?, &ingestion.substance)?;
+    dosage.classify(dose.value, unit)
+}
+
+/// Dose with its unit, volumes and custom units also show their mass if it is known.
+pub fn format_dose(ingestion: &Ingestion) -> String {
+    let dose = &ingestion.dose;
+    let mass = match dose.unit.dimension() {
+        Dimension::Mass => None,
+        Dimension::Volume | Dimension::Count => dose.milligrams(&ingestion.substance),
+    };
+    match mass {
+        Some(mass) => format!("{} (~{}mg)", dose, (mass * 1000.0).round() / 1000.0),
+        None => dose.to_string(),
+    }
+}
+
+/// Makes sure a custom unit is defined for the substance before logging a dose in it.
+pub fn check_dose_unit(dose: &Dose, substance: &Substance) {
+    if let DoseUnit::Custom(unit) = &dose.unit {
+        if substance.find_unit(unit).is_none() {
+            eprintln!(
+                "Unknown unit '{}' for {}, define it with `edit-substance \"{}\" --unit {}=<dose>`",
+                unit, substance.name, substance.name, unit
+            );
+            exit(1);
+        }
+    }
+}
+
+pub fn format_dose_tier(ingestion: &Ingestion) -> String {
+    match dose_tier(ingestion) {
+        Some(tier) => format!(" ({})", tier),
+        None => String::new(),
+    }
+}
+
+/// Warns loudly about heavy or stronger doses.
+///
+/// Returns `false` if the user declined to log the ingestion.
+pub fn check_dose_tier(ingestion: &Ingestion, yes: bool) -> bool {
+    let tier = match dose_tier(ingestion) {
+        Some(tier) if tier.is_excessive() => tier,
+        _ => return true,
+    };
+    println!(
+        "!!! WARNING: {} of {} is a {} dose !!!",
+        ingestion.dose,
+        ingestion.substance.name,
+        tier.to_string().to_uppercase()
+    );
+    if yes {
+        return true;
+    }
+    crate::util::ensure_interactive("--yes for a heavy dose");
+    inquire::prompt_confirmation("Do you really want to log this ingestion? [y/N]").unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_dose, parse_ingestion_datetime, with_current_substances};
+    use crate::ingestions::{Dose, Ingestion, IngestionMethod};
+    use crate::substances::{Substance, SubstanceClass};
+    use crate::units::{Concentration, CustomUnit, DoseUnit};
+    use chrono::{NaiveDate, NaiveTime, Utc};
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    fn gbl(concentration: Option<Concentration>, units: Vec<CustomUnit>) -> Substance {
+        Substance {
+            name: "GBL".to_string(),
+            substance_class: SubstanceClass::Depressant,
+            concentration,
+            units,
+        }
+    }
+
+    #[test]
+    fn converts_with_the_current_substance() {
+        let ingestion = |unit: DoseUnit, value: f64| Ingestion {
+            substance: gbl(None, vec![]),
+            dose: Dose { unit, value },
+            ingestion_method: IngestionMethod::Oral,
+            time: NaiveTime::from_hms_opt(20, 0, 0).unwrap(),
+            date: NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(),
+        };
+        let ml = Uuid::new_v4();
+        let cap = Uuid::new_v4();
+        let ingestions = [
+            (ml, ingestion(DoseUnit::Ml, 1.5)),
+            (cap, ingestion(DoseUnit::Custom("cap".to_string()), 2.0)),
+        ]
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+        assert_eq!(format_dose(&ingestions[&ml]), "1.5ml");
+
+        // Concentration and unit were added with edit-substance after logging
+        let current = gbl(
+            Some(Concentration {
+                milligrams_per_ml: 1120.0,
+            }),
+            vec![CustomUnit {
+                name: "cap".to_string(),
+                amount: 0.5,
+                unit: DoseUnit::Ml,
+            }],
+        );
+        let substances = [(Uuid::new_v4(), current)].into_iter().collect();
+        let ingestions = with_current_substances(ingestions, &substances);
+        assert_eq!(format_dose(&ingestions[&ml]), "1.5ml (~1680mg)");
+        assert_eq!(format_dose(&ingestions[&cap]), "2 cap (~1120mg)");
+
+        // Removed substances keep the copy logged with the ingestion
+        let ingestions = with_current_substances(ingestions, &Default::default());
+        assert_eq!(format_dose(&ingestions[&ml]), "1.5ml (~1680mg)");
+    }
+
+    #[test]
+    fn parses_ingestion_times_as_utc() {
+        let datetime = parse_ingestion_datetime("2024-01-01 21:30").unwrap();
+        assert_eq!(
+            datetime,
+            NaiveDate::from_ymd_opt(2024, 1, 1)
+                .unwrap()
+                .and_hms_opt(21, 30, 0)
+                .unwrap()
+        );
+
+        // A bare time is on today's date in UTC, whatever the local timezone is
+        let today = Utc::now().naive_utc().date();
+        let datetime = parse_ingestion_datetime(" 21:30 ").unwrap();
+        assert_eq!(
+            datetime,
+            today.and_time(NaiveTime::from_hms_opt(21, 30, 0).unwrap())
+        );
+
+        assert!(parse_ingestion_datetime("9:30pm").is_err());
+    }
+}
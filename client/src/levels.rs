@@ -0,0 +1,202 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use std::process::exit;
+
+use crate::drugs_parser::{parse_half_life, TimeRange, DRUGS};
+use crate::ingestions::Dose;
+use crate::ingestions_util::{ensure_ingestion_files, ingestion_datetime, is_same_substance};
+use crate::units::{Dimension, DoseUnit};
+use crate::util::{format_span, wrap_text, MAX_HOURS};
+
+const WIDTH: usize = 80;
+/// Number of rows in the estimate table after the one for now.
+const STEPS: i64 = 12;
+
+/// Parses `--half-life` values like `20h` or `30-100 hours`.
+pub fn parse_half_life_arg(value: &str) -> Result<TimeRange, String> {
+    match parse_half_life(value) {
+        Some(half_life) if half_life.max <= MAX_HOURS as f64 => Ok(half_life),
+        Some(_) => Err(format!(
+            "Half-life `{}` is too long, at most {} hours are supported",
+            value, MAX_HOURS
+        )),
+        None => Err(format!(
+            "Invalid half-life `{}`, use a value like `20h` or `30-100 hours`",
+            value
+        )),
+    }
+}
+
+/// Parses `--hours`, a positive number of hours up to `MAX_HOURS`.
+pub fn parse_hours_arg(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(hours) if hours > 0.0 && hours <= MAX_HOURS as f64 => Ok(hours),
+        _ => Err(format!(
+            "Invalid number of hours `{}`, use a positive number up to {}",
+            value, MAX_HOURS
+        )),
+    }
+}
+
+/// Amount of a dose left `hours` after it was taken, assuming first-order elimination.
+fn remaining(amount: f64, hours: f64, half_life: f64) -> f64 {
+    if hours < 0.0 {
+        0.0
+    } else {
+        amount * 0.5_f64.powf(hours / half_life)
+    }
+}
+
+/// Estimates how much of a substance is left from every logged ingestion of it.
+pub fn estimate_levels(
+    name: &str,
+    half_life: Option<TimeRange>,
+    hours: Option<f64>,
+) -> Result<(), std::io::Error> {
+    let drug = DRUGS.get(name);
+    let half_life = match half_life.or_else(|| {
+        let half_life = drug?.properties.half_life.as_ref()?;
+        parse_half_life(half_life)
+    }) {
+        Some(half_life) => half_life,
+        None => {
+            eprintln!(
+                "No half-life known for '{}', pass one with --half-life, e.g. `--half-life 30-100h`",
+                name
+            );
+            exit(1);
+        }
+    };
+
+    let now = Utc::now().naive_utc();
+    let ingestions = ensure_ingestion_files();
+    let mut taken = ingestions
+        .values()
+        .filter(|ingestion| {
+            is_same_substance(&ingestion.substance.name, name)
+                && ingestion_datetime(ingestion) <= now
+        })
+        .collect::<Vec<_>>();
+    if taken.is_empty() {
+        eprintln!("No ingestions of '{}' logged!", name);
+        exit(1);
+    }
+    taken.sort_by_key(|ingestion| ingestion_datetime(ingestion));
+
+    // Show amounts in the unit of the latest dose if it is a mass
+    let unit = match &taken.last().unwrap().dose.unit {
+        unit if unit.dimension() == Dimension::Mass => unit.clone(),
+        _ => DoseUnit::Mg,
+    };
+    let mut doses: Vec<(NaiveDateTime, f64)> = Vec::new();
+    for ingestion in &taken {
+        match ingestion.dose.convert(unit.clone(), &ingestion.substance) {
+            Some(dose) => doses.push((ingestion_datetime(ingestion), dose.value)),
+            None => println!(
+                "Skipping {} taken at {}, it can't be converted to {}",
+                ingestion.dose,
+                ingestion_datetime(ingestion).format("%Y-%m-%d %H:%M"),
+                unit
+            ),
+        }
+    }
+    if doses.is_empty() {
+        eprintln!("No ingestions of '{}' with a convertible dose!", name);
+        exit(1);
+    }
+    let total: f64 = doses.iter().map(|(_, amount)| amount).sum();
+    let level = |time: NaiveDateTime, half_life: f64| -> f64 {
+        doses
+            .iter()
+            .map(|(taken, amount)| {
+                let hours = (time - *taken).num_seconds() as f64 / 3600.0;
+                remaining(*amount, hours, half_life)
+            })
+            .sum()
+    };
+
+    println!(
+        "{}\n",
+        wrap_text(
+            "ROUGH ESTIMATE for harm reduction only. Assumes the whole dose is absorbed \
+             instantly and eliminated with a constant half-life, real levels depend on your \
+             metabolism, the dose form, active metabolites and other substances. Do not rely on \
+             it to decide whether redosing is safe.",
+            WIDTH,
+            ""
+        )
+    );
+    println!(
+        "{}: {} {}, {} in total, half-life {}",
+        drug.map_or(name, |drug| drug.pretty_name.as_str()),
+        doses.len(),
+        if doses.len() == 1 {
+            "ingestion"
+        } else {
+            "ingestions"
+        },
+        format_amount(total, &unit),
+        format_half_life(half_life)
+    );
+    if half_life.min != half_life.max {
+        println!("Ranges go from the shortest to the longest half-life.");
+    }
+    println!();
+
+    // Look far enough ahead for most of it to be gone with the longest half-life
+    let horizon = hours.unwrap_or((half_life.max * 5.0).ceil());
+    println!("{:<18}{:<16}{:<26}Remaining", "Time", "", "Amount");
+    for step in 0..=STEPS {
+        let offset = Duration::minutes((horizon * 60.0 * step as f64 / STEPS as f64) as i64);
+        let time = now + offset;
+        let fast = level(time, half_life.min);
+        let slow = level(time, half_life.max);
+        let relative = if step == 0 {
+            "now".to_string()
+        } else {
+            format!("+{}", format_span(offset))
+        };
+        let amount = if half_life.min == half_life.max {
+            format_amount(slow, &unit)
+        } else {
+            format!(
+                "{} - {}",
+                format_amount(fast, &unit),
+                format_amount(slow, &unit)
+            )
+        };
+        let fraction = if half_life.min == half_life.max {
+            format!("{:.0}%", slow / total * 100.0)
+        } else {
+            format!(
+                "{:.0}% - {:.0}%",
+                fast / total * 100.0,
+                slow / total * 100.0
+            )
+        };
+        println!(
+            "{:<18}{:<16}{:<26}{}",
+            time.format("%Y-%m-%d %H:%M"),
+            relative,
+            amount,
+            fraction
+        );
+    }
+    Ok(())
+}
+
+fn format_amount(amount: f64, unit: &DoseUnit) -> String {
+    let dose = Dose {
+        unit: unit.clone(),
+        value: (amount * 100.0).round() / 100.0,
+    };
+    dose.to_string()
+}
+
+fn format_half_life(half_life: TimeRange) -> String {
+    let span = |hours: f64| format_span(Duration::minutes((hours * 60.0).round() as i64));
+    if half_life.min == half_life.max {
+        span(half_life.min)
+    } else {
+        format!("{} - {}", span(half_life.min), span(half_life.max))
+    }
+}
@@ -4,6 +4,7 @@ use clap::{Command, Parser, Subcommand};
 use clap_complete::aot::{generate, Generator, Shell};
 use lazy_static::lazy_static;
 use std::io;
+use std::io::IsTerminal;
 
 lazy_static! {
     pub static ref HOME: String = std::env::var("HOME").unwrap();
@@ -15,12 +16,22 @@ lazy_static! {
 }
 mod util;
 
+mod benzo;
 mod drugs_parser;
+mod encryption;
+mod format;
+mod info;
 
 mod ingestions;
 mod ingestions_util;
+mod levels;
+mod schema;
+mod store;
 mod substance_util;
 mod substances;
+mod timeline;
+mod tolerance;
+mod units;
 
 // mod drug_parser;
 
@@ -36,7 +47,70 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     /// Adds ingestion
-    AddIngestion,
+    AddIngestion {
+        /// Name of the ingested substance
+        #[arg(long)]
+        substance: Option<String>,
+
+        /// Amount consumed including the unit, e.g. `20mg` or `2 tabs`
+        #[arg(long)]
+        dose: Option<ingestions::Dose>,
+
+        /// How the substance was ingested, e.g. `oral`
+        #[arg(long)]
+        method: Option<ingestions::IngestionMethod>,
+
+        /// Time of ingestion in UTC as `YYYY-MM-DD HH:MM` or `HH:MM` for today
+        #[arg(long, value_parser = ingestions_util::parse_ingestion_datetime)]
+        at: Option<chrono::NaiveDateTime>,
+
+        /// Log the ingestion without confirming a dangerous combination or a heavy dose
+        #[arg(long)]
+        yes: bool,
+    },
+
+    /// Shows onset, peak and end of recent ingestions that are still in effect
+    Active,
+
+    /// Draws a timeline of recent ingestions and their effects
+    Timeline {
+        /// How many hours to look back
+        #[arg(
+            long,
+            default_value_t = 24,
+            value_parser = clap::value_parser!(i64).range(1..=util::MAX_HOURS)
+        )]
+        hours: i64,
+
+        /// Only use ASCII characters
+        #[arg(long)]
+        ascii: bool,
+    },
+
+    /// Estimates how much of a substance is left based on its half-life
+    EstimateLevels {
+        /// Name of the substance
+        name: String,
+
+        /// Half-life to use instead of the drug database, e.g. `30-100h`
+        #[arg(long, value_parser = levels::parse_half_life_arg)]
+        half_life: Option<drugs_parser::TimeRange>,
+
+        /// How many hours to look ahead, defaults to five half-lives
+        #[arg(long, value_parser = levels::parse_hours_arg)]
+        hours: Option<f64>,
+    },
+
+    /// Totals recent benzodiazepine doses in diazepam-equivalent milligrams
+    BenzoEquiv {
+        /// How many hours to look back
+        #[arg(
+            long,
+            default_value_t = benzo::DEFAULT_WINDOW_HOURS,
+            value_parser = clap::value_parser!(i64).range(1..=util::MAX_HOURS)
+        )]
+        hours: i64,
+    },
 
     /// Edits an ingestion
     EditIngestion,
@@ -45,19 +119,101 @@ enum Commands {
     ListIngestions,
 
     /// Remove ingestion
-    RemoveIngestion,
+    RemoveIngestion {
+        /// UUID of the ingestion to remove, skips the interactive selection
+        #[arg(long)]
+        id: Option<uuid::Uuid>,
+    },
 
     /// Adds substance
-    AddSubstance,
+    AddSubstance {
+        /// Name of the substance
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Class of the substance, e.g. `stimulant`
+        #[arg(long)]
+        class: Option<substances::SubstanceClass>,
+
+        /// Mass per ml for liquids, e.g. `1.12g/ml`
+        #[arg(long)]
+        concentration: Option<units::Concentration>,
+
+        /// Custom unit for doses, e.g. `tab=100ug`, can be repeated
+        #[arg(long = "unit")]
+        units: Vec<units::CustomUnit>,
+    },
 
     /// Edits an substance
-    EditSubstance,
+    EditSubstance {
+        /// Name of the substance to edit
+        name: Option<String>,
+
+        /// New name of the substance
+        #[arg(long)]
+        rename: Option<String>,
+
+        /// New class of the substance
+        #[arg(long)]
+        class: Option<substances::SubstanceClass>,
+
+        /// New mass per ml for liquids, e.g. `100ug/ml`
+        #[arg(long)]
+        concentration: Option<units::Concentration>,
+
+        /// Adds or replaces a custom unit, e.g. `drink=14g`, can be repeated
+        #[arg(long = "unit")]
+        units: Vec<units::CustomUnit>,
+
+        /// Removes a custom unit by name, can be repeated
+        #[arg(long = "remove-unit")]
+        remove_units: Vec<String>,
+    },
 
     /// List substances
     ListSubstances,
 
+    /// Import substances from the bundled drug database
+    ImportSubstances {
+        /// Only import commonly used substances
+        #[arg(long)]
+        common: bool,
+    },
+
     /// Remove substance
-    RemoveSubstance,
+    RemoveSubstance {
+        /// Name of the substance to remove
+        name: Option<String>,
+
+        /// Skip the confirmation prompt
+        #[arg(long)]
+        yes: bool,
+    },
+
+    /// Show harm reduction information about a substance
+    SubstanceInfo {
+        /// Name or alias of the substance
+        name: String,
+    },
+
+    /// Check interactions between substances or combo groups like `opioids`
+    CheckCombo {
+        /// Names, aliases or combo groups to check pairwise
+        #[arg(required = true, num_args = 2..)]
+        names: Vec<String>,
+    },
+
+    /// Encrypt substances and ingestions with a passphrase
+    Encrypt,
+
+    /// Remove the passphrase and store substances and ingestions unencrypted
+    Decrypt,
+
+    /// Change the passphrase of encrypted data
+    ChangePassphrase,
+
+    /// Print a command keeping the data unlocked in this shell, use as `eval "$(meowlog unlock)"`
+    Unlock,
 
     /// Generate shell completions
     GenerateCompletions { shell: String },
@@ -65,6 +221,7 @@ enum Commands {
 
 use clap::CommandFactory;
 use std::str::FromStr;
+use store::FileStore;
 fn build_cli_command() -> Command {
     Cli::command()
 }
@@ -74,20 +231,57 @@ fn print_completions<G: Generator>(gen: G, cmd: &mut Command) {
 }
 
 fn main() {
-    drugs_parser::parse();
     ensure_files();
 
     let cli = Cli::parse();
 
     match cli.command {
-        Some(Commands::AddIngestion) => ingestions::add_ingestion(),
+        Some(Commands::AddIngestion {
+            substance,
+            dose,
+            method,
+            at,
+            yes,
+        }) => ingestions::add_ingestion(substance, dose, method, at, yes),
+        Some(Commands::Active) => timeline::active().unwrap(),
+        Some(Commands::Timeline { hours, ascii }) => timeline::chart(hours, ascii).unwrap(),
+        Some(Commands::EstimateLevels {
+            name,
+            half_life,
+            hours,
+        }) => levels::estimate_levels(&name, half_life, hours).unwrap(),
+        Some(Commands::BenzoEquiv { hours }) => benzo::benzo_equiv(hours).unwrap(),
         Some(Commands::EditIngestion) => ingestions::edit_ingestion().unwrap(),
         Some(Commands::ListIngestions) => ingestions::list_ingestions().unwrap(),
-        Some(Commands::RemoveIngestion) => {}
-        Some(Commands::AddSubstance) => substances::add_substance().unwrap(),
-        Some(Commands::EditSubstance) => substances::edit_substance().unwrap(),
+        Some(Commands::RemoveIngestion { id }) => ingestions::remove_ingestion(id).unwrap(),
+        Some(Commands::AddSubstance {
+            name,
+            class,
+            concentration,
+            units,
+        }) => substances::add_substance(name, class, concentration, units).unwrap(),
+        Some(Commands::EditSubstance {
+            name,
+            rename,
+            class,
+            concentration,
+            units,
+            remove_units,
+        }) => substances::edit_substance(name, rename, class, concentration, units, remove_units)
+            .unwrap(),
         Some(Commands::ListSubstances) => substances::list_substances().unwrap(),
-        Some(Commands::RemoveSubstance) => substances::remove_substance().unwrap(),
+        Some(Commands::ImportSubstances { common }) => {
+            substances::import_substances(common).unwrap()
+        }
+        Some(Commands::RemoveSubstance { name, yes }) => {
+            substances::remove_substance(name, yes).unwrap()
+        }
+        Some(Commands::SubstanceInfo { name }) => info::substance_info(&name).unwrap(),
+        Some(Commands::CheckCombo { names }) => info::check_combo(&names).unwrap(),
+        Some(Commands::Encrypt) => encryption::encrypt().unwrap(),
+        Some(Commands::Decrypt) => encryption::decrypt().unwrap(),
+        Some(Commands::ChangePassphrase) => encryption::change_passphrase().unwrap(),
+        Some(Commands::Unlock) => encryption::unlock().unwrap(),
         Some(Commands::GenerateCompletions { shell }) => {
             let mut cmd = Cli::command();
             eprintln!("Generating completion file for {shell}...");
@@ -114,13 +308,22 @@ fn ensure_files() {
             }
         }
     }
-    if !util::path_exists(SUBSTANCES_FILE.to_string()) {
+    // A missing file with a backup left is recovered when loading, creating it would hide that
+    if !FileStore::substances().exists() {
         match substance_util::create_substances_file() {
             Ok(_) => {
                 println!(
                     "Created substances file at {:?}",
                     SUBSTANCES_FILE.to_string()
-                )
+                );
+                if std::io::stdin().is_terminal()
+                    && inquire::prompt_confirmation(
+                        "Import common substances from the bundled drug database? [y/N]",
+                    )
+                    .unwrap()
+                {
+                    substances::import_substances(true).unwrap();
+                }
             }
             Err(_) => {
                 eprintln!("Could not create substances file");
@@ -128,7 +331,7 @@ fn ensure_files() {
             }
         };
     }
-    if !util::path_exists(INGESTIONS_FILE.to_string()) {
+    if !FileStore::ingestions().exists() {
         match ingestions::create_ingestions_file() {
             Ok(_) => {
                 println!(
@@ -0,0 +1,277 @@
+//! Schema versions of the data files and the migrations from older ones to the current one.
+//!
+//! - v0: dose units were plain strings
+//! - v1: typed dose units and a per-substance concentration, but no custom units
+//! - v2: custom units per substance
+//!
+//! Files written before the version was recorded in the header are matched against every
+//! layout from the newest one down.
+
+use bincode::Options;
+use chrono::{NaiveDate, NaiveTime};
+use serde::{de::DeserializeOwned, Deserialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+use uuid::Uuid;
+
+use crate::ingestions::{Dose, Ingestion, IngestionMethod};
+use crate::substances::{Substance, SubstanceClass};
+use crate::units::{Concentration, DoseUnit};
+
+/// Schema version written by this build. Any change to `Ingestion`, `Dose` or `Substance` needs
+/// a new version along with a migration from the previous one.
+pub const SCHEMA: u16 = 2;
+
+/// Records along with the schema version they were decoded from.
+pub type Versioned<T> = (HashMap<Uuid, T>, u16);
+
+#[derive(Deserialize)]
+struct SubstanceV0 {
+    name: String,
+    substance_class: SubstanceClass,
+}
+
+#[derive(Deserialize)]
+struct DoseV0 {
+    unit: String,
+    value: f64,
+}
+
+#[derive(Deserialize)]
+struct IngestionV0 {
+    substance: SubstanceV0,
+    dose: DoseV0,
+    ingestion_method: IngestionMethod,
+    time: NaiveTime,
+    date: NaiveDate,
+}
+
+#[derive(Deserialize)]
+struct SubstanceV1 {
+    name: String,
+    substance_class: SubstanceClass,
+    concentration: Option<Concentration>,
+}
+
+#[derive(Deserialize)]
+struct IngestionV1 {
+    substance: SubstanceV1,
+    dose: Dose,
+    ingestion_method: IngestionMethod,
+    time: NaiveTime,
+    date: NaiveDate,
+}
+
+impl From<SubstanceV0> for SubstanceV1 {
+    fn from(substance: SubstanceV0) -> Self {
+        SubstanceV1 {
+            name: substance.name,
+            substance_class: substance.substance_class,
+            concentration: None,
+        }
+    }
+}
+
+impl TryFrom<IngestionV0> for IngestionV1 {
+    type Error = ();
+
+    fn try_from(ingestion: IngestionV0) -> Result<Self, Self::Error> {
+        // Custom units did not exist yet, so anything else is not a v0 file
+        let unit = match DoseUnit::from_str(&ingestion.dose.unit) {
+            Ok(DoseUnit::Custom(_)) | Err(_) => return Err(()),
+            Ok(unit) => unit,
+        };
+        Ok(IngestionV1 {
+            substance: ingestion.substance.into(),
+            dose: Dose {
+                unit,
+                value: ingestion.dose.value,
+            },
+            ingestion_method: ingestion.ingestion_method,
+            time: ingestion.time,
+            date: ingestion.date,
+        })
+    }
+}
+
+impl From<SubstanceV1> for Substance {
+    fn from(substance: SubstanceV1) -> Self {
+        Substance {
+            name: substance.name,
+            substance_class: substance.substance_class,
+            concentration: substance.concentration,
+            units: vec![],
+        }
+    }
+}
+
+impl From<IngestionV1> for Ingestion {
+    fn from(ingestion: IngestionV1) -> Self {
+        Ingestion {
+            substance: ingestion.substance.into(),
+            dose: ingestion.dose,
+            ingestion_method: ingestion.ingestion_method,
+            time: ingestion.time,
+            date: ingestion.date,
+        }
+    }
+}
+
+/// Decodes `bytes` as exactly one `T`, unlike `bincode::deserialize` which ignores trailing
+/// bytes and would happily read one layout as another.
+fn decode_exact<T: DeserializeOwned>(bytes: &[u8]) -> Option<T> {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+        .deserialize(bytes)
+        .ok()
+}
+
+/// Converts every record to the next schema version.
+fn migrate<A, B: From<A>>(records: HashMap<Uuid, A>) -> HashMap<Uuid, B> {
+    records.into_iter().map(|(id, r)| (id, r.into())).collect()
+}
+
+/// Decodes records of version `schema`, or of the newest version that fits if it is unknown,
+/// along with the version they were decoded from.
+fn decode_versioned<T>(
+    schema: Option<u16>,
+    bytes: &[u8],
+    decode: impl Fn(u16, &[u8]) -> Option<HashMap<Uuid, T>>,
+) -> Option<Versioned<T>> {
+    match schema {
+        Some(schema) => Some((decode(schema, bytes)?, schema)),
+        None => (0..=SCHEMA)
+            .rev()
+            .find_map(|schema| Some((decode(schema, bytes)?, schema))),
+    }
+}
+
+fn substances_at(schema: u16, bytes: &[u8]) -> Option<HashMap<Uuid, Substance>> {
+    match schema {
+        0 => {
+            let v0: HashMap<Uuid, SubstanceV0> = decode_exact(bytes)?;
+            Some(migrate(migrate::<_, SubstanceV1>(v0)))
+        }
+        1 => Some(migrate(decode_exact::<HashMap<Uuid, SubstanceV1>>(bytes)?)),
+        2 => decode_exact(bytes),
+        _ => None,
+    }
+}
+
+fn ingestions_at(schema: u16, bytes: &[u8]) -> Option<HashMap<Uuid, Ingestion>> {
+    match schema {
+        0 => {
+            let v0: HashMap<Uuid, IngestionV0> = decode_exact(bytes)?;
+            let v1 = v0
+                .into_iter()
+                .map(|(id, i)| Some((id, IngestionV1::try_from(i).ok()?)))
+                .collect::<Option<HashMap<_, _>>>()?;
+            Some(migrate(v1))
+        }
+        1 => Some(migrate(decode_exact::<HashMap<Uuid, IngestionV1>>(bytes)?)),
+        2 => decode_exact(bytes),
+        _ => None,
+    }
+}
+
+pub fn decode_substances(schema: Option<u16>, bytes: &[u8]) -> Option<Versioned<Substance>> {
+    decode_versioned(schema, bytes, substances_at)
+}
+
+pub fn decode_ingestions(schema: Option<u16>, bytes: &[u8]) -> Option<Versioned<Ingestion>> {
+    decode_versioned(schema, bytes, ingestions_at)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Serialize;
+
+    #[derive(Serialize)]
+    struct IngestionOut<S, D> {
+        substance: S,
+        dose: D,
+        ingestion_method: IngestionMethod,
+        time: NaiveTime,
+        date: NaiveDate,
+    }
+
+    fn ingestions<S: Serialize, D: Serialize>(entries: Vec<(S, D)>) -> Vec<u8> {
+        let entries = entries
+            .into_iter()
+            .map(|(substance, dose)| {
+                let ingestion = IngestionOut {
+                    substance,
+                    dose,
+                    ingestion_method: IngestionMethod::Oral,
+                    time: NaiveTime::from_hms_opt(20, 0, 0).unwrap(),
+                    date: NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(),
+                };
+                (Uuid::new_v4(), ingestion)
+            })
+            .collect::<HashMap<_, _>>();
+        bincode::serialize(&entries).unwrap()
+    }
+
+    fn doses(bytes: &[u8]) -> Vec<(DoseUnit, f64)> {
+        let mut doses = decode_ingestions(None, bytes)
+            .unwrap()
+            .0
+            .into_values()
+            .map(|i| (i.dose.unit, i.dose.value))
+            .collect::<Vec<_>>();
+        doses.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        doses
+    }
+
+    #[test]
+    fn reads_string_dose_units() {
+        let substance = ("GHB", SubstanceClass::Depressant);
+        let bytes = ingestions(vec![
+            (substance, ("mg", 20.0)),
+            (substance, ("ml", 1.5)),
+            (substance, ("ug", 100.0)),
+        ]);
+        assert_eq!(
+            doses(&bytes),
+            vec![
+                (DoseUnit::Ug, 100.0),
+                (DoseUnit::Mg, 20.0),
+                (DoseUnit::Ml, 1.5)
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_substances_without_custom_units() {
+        let gbl = Concentration {
+            milligrams_per_ml: 1120.0,
+        };
+        let substance = ("GBL", SubstanceClass::Depressant, Some(gbl));
+        let bytes = ingestions(vec![
+            (substance, (DoseUnit::Ml, 1.5)),
+            (substance, (DoseUnit::G, 1.0)),
+        ]);
+        assert_eq!(doses(&bytes), vec![(DoseUnit::G, 1.0), (DoseUnit::Ml, 1.5)]);
+        let (current, schema) = decode_ingestions(None, &bytes).unwrap();
+        assert_eq!(schema, 1);
+        assert!(current
+            .values()
+            .all(|i| i.substance.concentration == Some(gbl) && i.substance.units.is_empty()));
+
+        let bytes = bincode::serialize(&current).unwrap();
+        assert_eq!(decode_ingestions(None, &bytes), Some((current, SCHEMA)));
+    }
+
+    #[test]
+    fn decodes_the_recorded_schema() {
+        let substance = ("GBL", SubstanceClass::Depressant, None::<Concentration>);
+        let bytes = ingestions(vec![(substance, (DoseUnit::Ml, 1.5))]);
+        assert_eq!(doses(&bytes), vec![(DoseUnit::Ml, 1.5)]);
+        assert!(decode_ingestions(Some(1), &bytes).is_some());
+        assert_eq!(decode_ingestions(Some(0), &bytes), None);
+        assert_eq!(decode_ingestions(Some(SCHEMA), &bytes), None);
+        assert_eq!(decode_ingestions(Some(SCHEMA + 1), &bytes), None);
+    }
+}
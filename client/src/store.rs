@@ -0,0 +1,705 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Error, ErrorKind, IsTerminal, Write};
+use std::path::Path;
+use std::process::exit;
+use uuid::Uuid;
+
+use crate::encryption;
+use crate::format::{self, FrameError};
+use crate::ingestions::Ingestion;
+use crate::schema::{decode_ingestions, decode_substances, Versioned, SCHEMA};
+use crate::substances::Substance;
+use crate::util::path_exists;
+use crate::{INGESTIONS_FILE, SUBSTANCES_FILE};
+
+/// Storage for records keyed by UUID.
+///
+/// Backends only need to load and save every record at once, the other operations are built on
+/// top of those two.
+pub trait Store<T: Clone> {
+    /// Reads every record, an empty map if nothing was saved yet.
+    fn load(&self) -> Result<HashMap<Uuid, T>, Error>;
+
+    /// Replaces every record with `records`.
+    fn save(&mut self, records: &HashMap<Uuid, T>) -> Result<(), Error>;
+
+    fn get(&self, id: &Uuid) -> Result<Option<T>, Error> {
+        Ok(self.load()?.remove(id))
+    }
+
+    /// Adds `record` under a new UUID and returns it.
+    fn insert(&mut self, record: T) -> Result<Uuid, Error> {
+        let mut records = self.load()?;
+        let id = Uuid::new_v4();
+        records.insert(id, record);
+        self.save(&records)?;
+        Ok(id)
+    }
+
+    /// Replaces the record with `id`, which has to exist.
+    fn update(&mut self, id: Uuid, record: T) -> Result<(), Error> {
+        let mut records = self.load()?;
+        match records.get_mut(&id) {
+            Some(existing) => *existing = record,
+            None => return Err(Error::new(ErrorKind::NotFound, format!("No record {}", id))),
+        }
+        self.save(&records)
+    }
+
+    /// Removes the record with `id` and returns it, `None` if there was none.
+    fn delete(&mut self, id: &Uuid) -> Result<Option<T>, Error> {
+        let mut records = self.load()?;
+        let removed = records.remove(id);
+        if removed.is_some() {
+            self.save(&records)?;
+        }
+        Ok(removed)
+    }
+
+    /// Records matching `filter` in no particular order.
+    fn query(&self, filter: impl Fn(&T) -> bool) -> Result<Vec<(Uuid, T)>, Error> {
+        Ok(self
+            .load()?
+            .into_iter()
+            .filter(|(_, record)| filter(record))
+            .collect())
+    }
+}
+
+/// Records stored as a bincode encoded `HashMap<Uuid, T>` in a single file.
+///
+/// Files are framed by `format` so damage is detected and encrypted by `encryption` once a
+/// passphrase is set. Saves replace the file atomically and keep the previous generation next to
+/// it as `.bak`, which loads fall back to when the file itself is missing or damaged. Files of an
+/// older schema are upgraded when loaded, keeping the original as `.v<schema>.bak`.
+pub struct FileStore<T> {
+    path: String,
+    /// Decodes records of a schema version or guesses it, returning the version it decoded
+    decode: fn(Option<u16>, &[u8]) -> Option<Versioned<T>>,
+}
+
+impl FileStore<Substance> {
+    pub fn substances() -> Self {
+        FileStore {
+            path: SUBSTANCES_FILE.to_string(),
+            decode: decode_substances,
+        }
+    }
+}
+
+impl FileStore<Ingestion> {
+    pub fn ingestions() -> Self {
+        FileStore {
+            path: INGESTIONS_FILE.to_string(),
+            decode: decode_ingestions,
+        }
+    }
+}
+
+impl<T> FileStore<T> {
+    fn backup_path(&self) -> String {
+        format!("{}.bak", self.path)
+    }
+
+    fn corrupt_path(&self) -> String {
+        format!("{}.corrupt", self.path)
+    }
+
+    /// Where the original of a file upgraded from schema version `schema` is kept.
+    fn original_path(&self, schema: u16) -> String {
+        format!("{}.v{}.bak", self.path, schema)
+    }
+
+    /// Reads and decodes the file at `path`, `None` if it does not exist.
+    fn read(&self, path: &str) -> Result<Option<HashMap<Uuid, T>>, Error> {
+        Ok(self.read_versioned(path)?.map(|((records, _), _)| records))
+    }
+
+    /// Like `read`, along with the schema version the records were decoded from and whether the
+    /// file needs to be upgraded to record the current one.
+    fn read_versioned(&self, path: &str) -> Result<Option<(Versioned<T>, bool)>, Error> {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let corrupt = |reason: String| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("{} is corrupt: {}", path, reason),
+            )
+        };
+        let (schema, payload) = match format::decode(&bytes) {
+            Ok(Some(frame)) => (frame.schema, frame.payload),
+            Ok(None) => (None, &bytes[..]),
+            Err(e @ FrameError::UnsupportedVersion(_)) => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!("{} is unreadable: {}", path, e),
+                ))
+            }
+            Err(e) => return Err(corrupt(e.to_string())),
+        };
+        if let Some(schema) = schema.filter(|&schema| schema > SCHEMA) {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "{} was written by a newer meowlog (schema version {}, this one reads up to {})",
+                    path, schema, SCHEMA
+                ),
+            ));
+        }
+        let plaintext;
+        let payload = if encryption::is_sealed(payload) {
+            plaintext = encryption::unseal(path, payload)?;
+            &plaintext
+        } else {
+            payload
+        };
+        match (self.decode)(schema, payload) {
+            Some((records, decoded)) => Ok(Some(((records, decoded), schema != Some(SCHEMA)))),
+            None => Err(corrupt("its contents could not be decoded".to_string())),
+        }
+    }
+
+    /// Whether the file exists but fails its checksum, checked without decrypting it. Files of a
+    /// newer format aren't damaged.
+    fn is_damaged(&self) -> bool {
+        match std::fs::read(&self.path) {
+            Ok(bytes) => match format::decode(&bytes) {
+                Ok(Some(_)) => false,
+                Ok(None) => (self.decode)(None, &bytes).is_none(),
+                Err(FrameError::UnsupportedVersion(_)) => false,
+                Err(_) => true,
+            },
+            Err(e) => e.kind() != ErrorKind::NotFound,
+        }
+    }
+
+    /// Whether anything was saved before, counting a backup or an unfinished save.
+    pub fn exists(&self) -> bool {
+        [self.path.clone(), self.backup_path(), temp_path(&self.path)]
+            .into_iter()
+            .any(path_exists)
+    }
+
+    /// Whether the file is encrypted, without decrypting it.
+    pub fn is_sealed(&self) -> bool {
+        match std::fs::read(&self.path) {
+            Ok(bytes) => matches!(
+                format::decode(&bytes),
+                Ok(Some(frame)) if encryption::is_sealed(frame.payload)
+            ),
+            Err(_) => false,
+        }
+    }
+
+    /// Deletes the backup, originals of upgraded files and a damaged file set aside earlier.
+    pub fn remove_backups(&self) -> Result<(), Error> {
+        let originals = (0..=SCHEMA).map(|schema| self.original_path(schema));
+        for path in [self.backup_path(), self.corrupt_path()]
+            .into_iter()
+            .chain(originals)
+        {
+            match std::fs::remove_file(path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves a damaged file out of the way so it does not replace the backup on the next save.
+    fn set_aside(&self) -> Result<(), Error> {
+        if path_exists(self.path.clone()) {
+            std::fs::rename(&self.path, self.corrupt_path())?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the file with a copy of its backup, keeping the damaged file as `.corrupt`.
+    pub fn restore_backup(&self) -> Result<(), Error> {
+        let bytes = std::fs::read(self.backup_path())?;
+        self.set_aside()?;
+        write_atomic(&self.path, &self.backup_path(), &bytes)
+    }
+}
+
+impl<T: Serialize> FileStore<T> {
+    fn encode(&self, records: &HashMap<Uuid, T>) -> Result<Vec<u8>, Error> {
+        let bytes = bincode::serialize(records).map_err(Error::other)?;
+        Ok(format::encode(SCHEMA, &encryption::seal_for_session(bytes)))
+    }
+
+    /// Rewrites a file of schema version `schema` in the current one, keeping the original.
+    fn upgrade(&self, records: &HashMap<Uuid, T>, schema: u16) -> Result<(), Error> {
+        let original = self.original_path(schema);
+        if !path_exists(original.clone()) {
+            std::fs::copy(&self.path, original)?;
+        }
+        write_atomic(&self.path, &self.backup_path(), &self.encode(records)?)
+    }
+
+    /// Writes `records` next to the file without replacing it yet, see `commit_staged`.
+    pub fn stage(&self, records: &HashMap<Uuid, T>) -> Result<(), Error> {
+        write_synced(&temp_path(&self.path), &self.encode(records)?)
+    }
+
+    /// Replaces the file with what was staged, so several files can be written before any of
+    /// them is replaced.
+    pub fn commit_staged(&self) -> Result<(), Error> {
+        if self.is_damaged() {
+            self.set_aside()?;
+        }
+        commit(&self.path, &self.backup_path())
+    }
+
+    /// Deletes what was staged after a failed write.
+    pub fn discard_staged(&self) {
+        let _ = std::fs::remove_file(temp_path(&self.path));
+    }
+}
+
+impl<T: Clone + Serialize> Store<T> for FileStore<T> {
+    fn load(&self) -> Result<HashMap<Uuid, T>, Error> {
+        let primary = match self.read_versioned(&self.path) {
+            Ok(Some(((records, schema), outdated))) => {
+                if outdated {
+                    self.upgrade(&records, schema)?;
+                }
+                return Ok(records);
+            }
+            Ok(None) => Ok(()),
+            // A newer, locked or unreadable file must not be replaced by the older backup
+            Err(e) if e.kind() != ErrorKind::InvalidData => return Err(e),
+            Err(e) => Err(e),
+        };
+        let backup = self.backup_path();
+        match (primary, self.read(&backup)) {
+            (_, Ok(Some(records))) => Ok(records),
+            (Err(e), _) | (_, Err(e)) => Err(e),
+            (Ok(()), Ok(None)) => Ok(HashMap::new()),
+        }
+    }
+
+    fn save(&mut self, records: &HashMap<Uuid, T>) -> Result<(), Error> {
+        self.stage(records)?;
+        self.commit_staged()
+    }
+}
+
+/// Loads every record for a command.
+///
+/// A damaged file is reported along with an offer to restore it from the backup, the backup is
+/// used as is when that is declined and the command exits when neither can be read.
+pub fn load_or_exit<T: Clone + Serialize>(store: &FileStore<T>) -> HashMap<Uuid, T> {
+    if let Err(e) = store.read(&store.path) {
+        eprintln!("{}", e);
+        // The backup only helps with damage, not with a locked or unreadable file
+        if e.kind() != ErrorKind::InvalidData {
+            exit(1);
+        }
+        let backup_path = store.backup_path();
+        let backup = match store.read(&backup_path) {
+            Ok(Some(backup)) => backup,
+            _ => {
+                eprintln!("There is no usable backup at {} either.", backup_path);
+                exit(1);
+            }
+        };
+        let restore = std::io::stdin().is_terminal()
+            && inquire::prompt_confirmation(format!(
+                "Restore it from the backup {} with {} entries? The damaged file is kept as {}. [y/N]",
+                backup_path,
+                backup.len(),
+                store.corrupt_path()
+            ))
+            .unwrap();
+        if !restore {
+            eprintln!("Using the backup {} for now.", backup_path);
+        } else if let Err(e) = store.restore_backup() {
+            eprintln!("Could not restore {}: {}", store.path, e);
+            exit(1);
+        }
+        return backup;
+    }
+    match store.load() {
+        Ok(records) => records,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    }
+}
+
+/// Replaces the file at `path` with `bytes` so that a crash leaves either the old or the new
+/// contents behind, copying the old contents to `backup`.
+fn write_atomic(path: &str, backup: &str, bytes: &[u8]) -> Result<(), Error> {
+    write_synced(&temp_path(path), bytes)?;
+    commit(path, backup)
+}
+
+/// Where the next contents of `path` are written before they replace it. Renames are only
+/// atomic within a file system, so it sits next to the target.
+fn temp_path(path: &str) -> String {
+    format!("{}.tmp", path)
+}
+
+fn write_synced(path: &str, bytes: &[u8]) -> Result<(), Error> {
+    let mut file = File::create(path)?;
+    file.write_all(bytes)?;
+    file.sync_all()
+}
+
+/// Moves the temp file written for `path` over it, after copying the current contents to
+/// `backup`. The file itself stays in place throughout, so a crash never leaves it missing.
+fn commit(path: &str, backup: &str) -> Result<(), Error> {
+    if path_exists(path.to_string()) {
+        let backup_temp = temp_path(backup);
+        write_synced(&backup_temp, &std::fs::read(path)?)?;
+        std::fs::rename(&backup_temp, backup)?;
+    }
+    std::fs::rename(temp_path(path), path)?;
+    // The renames are only durable once the directory itself is synced
+    #[cfg(unix)]
+    if let Some(dir) = Path::new(path).parent() {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Records kept in memory only, for tests.
+#[cfg(test)]
+pub struct MemoryStore<T> {
+    records: HashMap<Uuid, T>,
+}
+
+#[cfg(test)]
+impl<T> Default for MemoryStore<T> {
+    fn default() -> Self {
+        MemoryStore {
+            records: HashMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl<T: Clone> Store<T> for MemoryStore<T> {
+    fn load(&self) -> Result<HashMap<Uuid, T>, Error> {
+        Ok(self.records.clone())
+    }
+
+    fn save(&mut self, records: &HashMap<Uuid, T>) -> Result<(), Error> {
+        self.records = records.clone();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{temp_path, write_synced, FileStore, MemoryStore, Store};
+    use crate::encryption::{self, KdfParams, Key};
+    use crate::format;
+    use crate::ingestions::{Dose, Ingestion, IngestionMethod};
+    use crate::schema::{decode_ingestions, decode_substances, SCHEMA};
+    use crate::substances::{Substance, SubstanceClass};
+    use crate::units::{Concentration, CustomUnit, DoseUnit};
+    use chrono::{NaiveDate, NaiveTime};
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    fn substance(name: &str) -> Substance {
+        Substance {
+            name: name.to_string(),
+            substance_class: SubstanceClass::Stimulant,
+            concentration: None,
+            units: vec![],
+        }
+    }
+
+    fn exercise(store: &mut impl Store<Substance>) {
+        assert!(store.load().unwrap().is_empty());
+        let caffeine = store.insert(substance("Caffeine")).unwrap();
+        let nicotine = store.insert(substance("Nicotine")).unwrap();
+        assert_eq!(store.get(&caffeine).unwrap(), Some(substance("Caffeine")));
+
+        store.update(nicotine, substance("Theacrine")).unwrap();
+        let found = store.query(|s| s.name.starts_with("Thea")).unwrap();
+        assert_eq!(found, vec![(nicotine, substance("Theacrine"))]);
+
+        assert_eq!(
+            store.delete(&caffeine).unwrap(),
+            Some(substance("Caffeine"))
+        );
+        assert_eq!(store.delete(&caffeine).unwrap(), None);
+        assert!(store.update(caffeine, substance("Caffeine")).is_err());
+        assert_eq!(store.load().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn memory_store_operations() {
+        exercise(&mut MemoryStore::default());
+    }
+
+    fn temp_file() -> String {
+        let path = std::env::temp_dir().join(format!("meowlog-{}.bin", Uuid::new_v4()));
+        path.to_string_lossy().to_string()
+    }
+
+    fn temp_store() -> FileStore<Substance> {
+        FileStore {
+            path: temp_file(),
+            decode: decode_substances,
+        }
+    }
+
+    fn remove_files<T>(store: FileStore<T>) {
+        std::fs::remove_file(&store.path).unwrap();
+        std::fs::remove_file(store.backup_path()).unwrap();
+    }
+
+    #[test]
+    fn file_store_operations() {
+        let mut store = temp_store();
+        exercise(&mut store);
+        let bytes = std::fs::read(&store.path).unwrap();
+        let frame = format::decode(&bytes).unwrap().unwrap();
+        assert_eq!(frame.schema, Some(SCHEMA));
+        assert_eq!(
+            decode_substances(frame.schema, frame.payload),
+            Some((store.load().unwrap(), SCHEMA))
+        );
+        remove_files(store);
+    }
+
+    #[test]
+    fn file_store_falls_back_to_backup() {
+        let mut store = temp_store();
+        let id = store.insert(substance("Caffeine")).unwrap();
+        store.update(id, substance("Theacrine")).unwrap();
+        assert!(!std::path::Path::new(&format!("{}.tmp", store.path)).exists());
+        let previous = store.read(&store.backup_path()).unwrap().unwrap();
+        assert_eq!(previous.get(&id), Some(&substance("Caffeine")));
+
+        // A crash before the temp file is moved into place leaves the file as it was
+        write_synced(&temp_path(&store.path), &[1, 2, 3]).unwrap();
+        assert_eq!(store.get(&id).unwrap(), Some(substance("Theacrine")));
+        std::fs::remove_file(temp_path(&store.path)).unwrap();
+
+        // A truncated write or a file lost some other way
+        std::fs::write(&store.path, [1, 2, 3]).unwrap();
+        assert_eq!(store.get(&id).unwrap(), Some(substance("Caffeine")));
+        std::fs::remove_file(&store.path).unwrap();
+        assert!(store.exists());
+        assert_eq!(store.get(&id).unwrap(), Some(substance("Caffeine")));
+
+        std::fs::write(store.backup_path(), [1, 2, 3]).unwrap();
+        assert!(store.load().is_err());
+        std::fs::remove_file(store.backup_path()).unwrap();
+        assert!(store.load().unwrap().is_empty());
+    }
+
+    #[test]
+    fn staged_writes_wait_for_commit() {
+        let mut store = temp_store();
+        let id = store.insert(substance("Caffeine")).unwrap();
+        let mut records = store.load().unwrap();
+        records.insert(id, substance("Theacrine"));
+
+        store.stage(&records).unwrap();
+        assert_eq!(store.get(&id).unwrap(), Some(substance("Caffeine")));
+        store.discard_staged();
+        assert!(!std::path::Path::new(&temp_path(&store.path)).exists());
+
+        store.stage(&records).unwrap();
+        store.commit_staged().unwrap();
+        assert_eq!(store.get(&id).unwrap(), Some(substance("Theacrine")));
+        remove_files(store);
+    }
+
+    #[test]
+    fn damaged_files_are_set_aside() {
+        let mut store = temp_store();
+        let id = store.insert(substance("Caffeine")).unwrap();
+        store.update(id, substance("Theacrine")).unwrap();
+
+        let mut bytes = std::fs::read(&store.path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        std::fs::write(&store.path, &bytes).unwrap();
+        let error = store.read(&store.path).unwrap_err().to_string();
+        assert!(error.contains(&store.path) && error.contains("checksum"));
+
+        store.restore_backup().unwrap();
+        assert_eq!(store.get(&id).unwrap(), Some(substance("Caffeine")));
+        assert_eq!(std::fs::read(store.corrupt_path()).unwrap(), bytes);
+
+        // Saving over a damaged file leaves the good backup alone
+        std::fs::write(&store.path, [1, 2, 3]).unwrap();
+        store.update(id, substance("Paraxanthine")).unwrap();
+        let backup = store.read(&store.backup_path()).unwrap().unwrap();
+        assert_eq!(backup.get(&id), Some(&substance("Caffeine")));
+        assert_eq!(std::fs::read(store.corrupt_path()).unwrap(), [1, 2, 3]);
+
+        std::fs::remove_file(store.corrupt_path()).unwrap();
+        remove_files(store);
+    }
+
+    /// Substances in the fixtures as of `schema`, older versions lack newer fields.
+    fn fixture_substances(schema: u16) -> HashMap<Uuid, Substance> {
+        let gbl = Substance {
+            name: "GBL".to_string(),
+            substance_class: SubstanceClass::Depressant,
+            concentration: (schema >= 1).then_some(Concentration {
+                milligrams_per_ml: 1120.0,
+            }),
+            units: vec![],
+        };
+        let mdma = Substance {
+            name: "MDMA".to_string(),
+            substance_class: SubstanceClass::Empathogen,
+            concentration: None,
+            units: match schema {
+                0 | 1 => vec![],
+                _ => vec![CustomUnit {
+                    name: "pill".to_string(),
+                    amount: 120.0,
+                    unit: DoseUnit::Mg,
+                }],
+            },
+        };
+        [(Uuid::from_u128(1), gbl), (Uuid::from_u128(2), mdma)]
+            .into_iter()
+            .collect()
+    }
+
+    fn fixture_ingestions(schema: u16) -> HashMap<Uuid, Ingestion> {
+        let substances = fixture_substances(schema);
+        let ingestion = |substance: u128, unit: DoseUnit, value: f64| Ingestion {
+            substance: substances[&Uuid::from_u128(substance)].clone(),
+            dose: Dose { unit, value },
+            ingestion_method: IngestionMethod::Oral,
+            time: NaiveTime::from_hms_opt(20, 0, 0).unwrap(),
+            date: NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(),
+        };
+        let mdma = match schema {
+            0 | 1 => ingestion(2, DoseUnit::Mg, 120.0),
+            _ => ingestion(2, DoseUnit::Custom("pill".to_string()), 1.0),
+        };
+        [
+            (Uuid::from_u128(3), ingestion(1, DoseUnit::Ml, 1.5)),
+            (Uuid::from_u128(4), mdma),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    /// Loads `fixture` of version `schema` and checks it was upgraded with the original kept.
+    fn migrate_fixture<T: Clone + serde::Serialize + PartialEq + std::fmt::Debug>(
+        fixture: &[u8],
+        schema: u16,
+        store: FileStore<T>,
+        expected: HashMap<Uuid, T>,
+    ) {
+        std::fs::write(&store.path, fixture).unwrap();
+        assert_eq!(store.load().unwrap(), expected);
+
+        let original = store.original_path(schema);
+        assert_eq!(std::fs::read(&original).unwrap(), fixture);
+        let bytes = std::fs::read(&store.path).unwrap();
+        let frame = format::decode(&bytes).unwrap().unwrap();
+        assert_eq!(frame.schema, Some(SCHEMA));
+        // Encrypted files are upgraded with the same key
+        assert_eq!(store.is_sealed(), encryption::session_key().is_some());
+        assert_eq!(store.load().unwrap(), expected);
+
+        store.remove_backups().unwrap();
+        assert!(!std::path::Path::new(&original).exists());
+        std::fs::remove_file(&store.path).unwrap();
+    }
+
+    #[test]
+    fn migrates_fixtures_of_every_schema() {
+        let substances: [(&[u8], u16); 4] = [
+            (include_bytes!("../fixtures/substances-v0.bin"), 0),
+            (include_bytes!("../fixtures/substances-v1.bin"), 1),
+            (include_bytes!("../fixtures/substances-v2.bin"), 2),
+            (include_bytes!("../fixtures/substances-v2-frame-v1.bin"), 2),
+        ];
+        for (fixture, schema) in substances {
+            let store = FileStore {
+                path: temp_file(),
+                decode: decode_substances,
+            };
+            migrate_fixture(fixture, schema, store, fixture_substances(schema));
+        }
+
+        let ingestions: [(&[u8], u16); 4] = [
+            (include_bytes!("../fixtures/ingestions-v0.bin"), 0),
+            (include_bytes!("../fixtures/ingestions-v1.bin"), 1),
+            (include_bytes!("../fixtures/ingestions-v2.bin"), 2),
+            (include_bytes!("../fixtures/ingestions-v2-frame-v1.bin"), 2),
+        ];
+        for (fixture, schema) in ingestions {
+            let store = FileStore {
+                path: temp_file(),
+                decode: decode_ingestions,
+            };
+            migrate_fixture(fixture, schema, store, fixture_ingestions(schema));
+        }
+    }
+
+    /// Files encrypted before the schema version was recorded, with cheap key derivation.
+    #[test]
+    fn migrates_sealed_fixtures() {
+        let params = KdfParams {
+            memory_kib: 64,
+            iterations: 1,
+            lanes: 1,
+        };
+        let key = Key::derive("meow", params, [7; 16]).unwrap();
+        encryption::set_session_key(Some(key));
+
+        let store = FileStore {
+            path: temp_file(),
+            decode: decode_substances,
+        };
+        let fixture = include_bytes!("../fixtures/substances-v2-sealed-frame-v1.bin");
+        migrate_fixture(fixture, 2, store, fixture_substances(2));
+
+        let store = FileStore {
+            path: temp_file(),
+            decode: decode_ingestions,
+        };
+        let fixture = include_bytes!("../fixtures/ingestions-v2-sealed-frame-v1.bin");
+        migrate_fixture(fixture, 2, store, fixture_ingestions(2));
+
+        encryption::set_session_key(None);
+    }
+
+    #[test]
+    fn rejects_newer_schemas() {
+        let mut store = temp_store();
+        store.insert(substance("Caffeine")).unwrap();
+        store.insert(substance("Nicotine")).unwrap();
+
+        // The backup must not stand in for a file this build can't read
+        let bytes = bincode::serialize(&fixture_substances(SCHEMA)).unwrap();
+        let newer = format::encode(SCHEMA + 1, &bytes);
+        std::fs::write(&store.path, &newer).unwrap();
+        let error = store.load().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::Unsupported);
+        assert!(error.to_string().contains("newer meowlog"));
+        assert!(!store.is_damaged());
+
+        let mut newer_frame = newer.clone();
+        newer_frame[4] = 0xff;
+        std::fs::write(&store.path, &newer_frame).unwrap();
+        let error = store.load().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::Unsupported);
+        assert!(!store.is_damaged());
+        remove_files(store);
+    }
+}
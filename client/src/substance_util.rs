@@ -1,20 +1,13 @@
 use std::collections::HashMap;
 use uuid::Uuid;
 
+use crate::encryption;
+use crate::store::{load_or_exit, FileStore, Store};
+use crate::substances::Substance;
 use crate::substances::SubstanceClass;
-use crate::util::path_exists;
-use crate::{substances::Substance, SUBSTANCES_FILE};
 
 pub fn ensure_substance_file() -> HashMap<Uuid, Substance> {
-    let substances_bytes_loaded_des: HashMap<Uuid, Substance>;
-    if path_exists(SUBSTANCES_FILE.to_string()) {
-        let substances_bytes_loaded = std::fs::read(SUBSTANCES_FILE.to_string()).unwrap();
-        substances_bytes_loaded_des = bincode::deserialize(&substances_bytes_loaded).unwrap();
-    } else {
-        std::fs::File::create(SUBSTANCES_FILE.to_string()).unwrap();
-        substances_bytes_loaded_des = HashMap::new();
-    }
-    substances_bytes_loaded_des
+    load_or_exit(&FileStore::substances())
 }
 
 pub fn get_substance_class(msg: &str, variants: Vec<SubstanceClass>) -> SubstanceClass {
@@ -23,28 +16,13 @@ pub fn get_substance_class(msg: &str, variants: Vec<SubstanceClass>) -> Substanc
 }
 
 pub fn substances_to_vec() -> Vec<String> {
-    let sub_read_res = std::fs::read(SUBSTANCES_FILE.to_string());
-    let sub_read = match sub_read_res {
-        Ok(sub_contents) => sub_contents,
-        Err(_) => {
-            println!("Error! Substance file does not exist. Creating file...");
-            let hash: HashMap<Uuid, Substance> = HashMap::new();
-            let hash_ser = bincode::serialize(&hash).unwrap();
-            std::fs::write(SUBSTANCES_FILE.to_string(), hash_ser).unwrap();
-            let ret: Vec<u8> = vec![];
-            ret
-        }
-    };
-    let sub_dec: HashMap<Uuid, Substance> = bincode::deserialize(&sub_read).unwrap();
-    let mut sub_vec: Vec<String> = vec![];
-    for (_id, substance) in sub_dec.clone().into_iter() {
-        sub_vec.push(substance.name);
-    }
-    sub_vec
+    ensure_substance_file()
+        .into_values()
+        .map(|substance| substance.name)
+        .collect()
 }
 
 pub fn create_substances_file() -> Result<(), std::io::Error> {
-    let hash: HashMap<Uuid, Substance> = HashMap::new();
-    let hash_ser = bincode::serialize(&hash).unwrap();
-    std::fs::write(SUBSTANCES_FILE.to_string(), hash_ser)
+    encryption::unlock_for_new_file();
+    FileStore::substances().save(&HashMap::new())
 }
@@ -1,18 +1,35 @@
 use serde::{self, Deserialize, Serialize};
 use std::collections::HashMap;
+use std::process::exit;
 use strum::IntoEnumIterator;
 use uuid::Uuid;
 
+use crate::drugs_parser::DRUGS;
+use crate::store::{FileStore, Store};
 use crate::substance_util::{ensure_substance_file, get_substance_class, substances_to_vec};
-use crate::SUBSTANCES_FILE;
+use crate::units::{Concentration, CustomUnit};
+use crate::util::ensure_interactive;
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Substance {
     pub name: String,
     pub substance_class: SubstanceClass,
+    /// Mass per millilitre, needed to compare doses logged in ml with reference doses
+    pub concentration: Option<Concentration>,
+    /// Units like `tab` or `drink` that doses of this substance can be logged in
+    pub units: Vec<CustomUnit>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, strum::Display, strum::EnumIter)]
+impl Substance {
+    pub fn find_unit(&self, name: &str) -> Option<&CustomUnit> {
+        self.units.iter().find(|unit| unit.matches(name))
+    }
+}
+
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, strum::Display, strum::EnumIter, strum::EnumString,
+)]
+#[strum(ascii_case_insensitive)]
 pub enum SubstanceClass {
     Stimulant,
     Depressant,
@@ -30,106 +47,207 @@ impl PartialEq for SubstanceClass {
         std::mem::discriminant(self) == std::mem::discriminant(other)
     }
 }
-pub fn add_substance() -> Result<(), std::io::Error> {
-    let mut substances_bytes_loaded_des: HashMap<Uuid, Substance> = ensure_substance_file();
-    let name = inquire::prompt_text("What is the substances name?").unwrap();
+pub fn add_substance(
+    name: Option<String>,
+    substance_class: Option<SubstanceClass>,
+    concentration: Option<Concentration>,
+    units: Vec<CustomUnit>,
+) -> Result<(), std::io::Error> {
+    let substances_bytes_loaded_des: HashMap<Uuid, Substance> = ensure_substance_file();
+    let name = match name {
+        Some(name) => name,
+        None => {
+            ensure_interactive("--name");
+            inquire::prompt_text("What is the substances name?").unwrap()
+        }
+    };
     if !substances_bytes_loaded_des.values().any(|x| x.name == name) {
-        let class_variants = SubstanceClass::iter().collect::<Vec<_>>();
-        let substance_class =
-            get_substance_class("What type of substance is this?", class_variants);
+        let substance_class = match substance_class {
+            Some(substance_class) => substance_class,
+            None => {
+                ensure_interactive("--class");
+                let class_variants = SubstanceClass::iter().collect::<Vec<_>>();
+                get_substance_class("What type of substance is this?", class_variants)
+            }
+        };
         let substance = Substance {
             name,
             substance_class,
+            concentration,
+            units,
         };
-        substances_bytes_loaded_des.insert(Uuid::new_v4(), substance);
-        let sub_enc = bincode::serialize(&substances_bytes_loaded_des).unwrap();
-        match std::fs::write(SUBSTANCES_FILE.to_string(), sub_enc) {
-            Ok(_) => Ok(()),
-            Err(e) => Err(e),
-        }
+        FileStore::substances().insert(substance)?;
+        Ok(())
     } else {
         println!("Substance already exists!");
         Ok(())
     }
 }
 
+pub fn import_substances(common_only: bool) -> Result<(), std::io::Error> {
+    let mut sub_dec: HashMap<Uuid, Substance> = ensure_substance_file();
+    let mut drugs = DRUGS
+        .drugs()
+        .filter(|drug| !common_only || drug.is_common())
+        .collect::<Vec<_>>();
+    drugs.sort_by(|a, b| a.pretty_name.cmp(&b.pretty_name));
+
+    let mut imported = 0;
+    let mut skipped = 0;
+    for drug in drugs {
+        // Substances added by hand may use an alias, e.g. "Xanax" for alprazolam
+        let exists = sub_dec.values().any(|x| {
+            x.name.eq_ignore_ascii_case(&drug.pretty_name)
+                || DRUGS.get(&x.name).is_some_and(|d| d.name == drug.name)
+        });
+        match drug.substance_class() {
+            Some(substance_class) if !exists => {
+                let substance = Substance {
+                    name: drug.pretty_name.clone(),
+                    substance_class,
+                    concentration: None,
+                    units: vec![],
+                };
+                sub_dec.insert(Uuid::new_v4(), substance);
+                imported += 1;
+            }
+            _ => skipped += 1,
+        }
+    }
+
+    FileStore::substances().save(&sub_dec)?;
+    println!(
+        "Imported {} substances ({} already existed or have no matching class)",
+        imported, skipped
+    );
+    Ok(())
+}
+
 pub fn list_substances() -> Result<(), std::io::Error> {
-    let sub_read = std::fs::read(SUBSTANCES_FILE.to_string()).unwrap();
-    let sub_dec: HashMap<Uuid, Substance> = bincode::deserialize(&sub_read).unwrap();
+    let sub_dec = ensure_substance_file();
     for (id, substance) in sub_dec.clone().into_iter() {
         println!(
-            "Name:  {}\nClass: {:?}\nUUID:  {:?}\n",
-            substance.name, substance.substance_class, id
+            "Name:  {}\nClass: {:?}",
+            substance.name, substance.substance_class
         );
+        if let Some(concentration) = substance.concentration {
+            println!("Conc.: {}", concentration);
+        }
+        if !substance.units.is_empty() {
+            let units = substance.units.iter().map(|u| u.to_string());
+            println!("Units: {}", units.collect::<Vec<_>>().join(", "));
+        }
+        println!("UUID:  {:?}\n", id);
     }
 
     Ok(())
 }
 
-pub fn remove_substance() -> Result<(), std::io::Error> {
-    let sub_read = std::fs::read(SUBSTANCES_FILE.to_string()).unwrap();
-    let mut sub_dec: HashMap<Uuid, Substance> = bincode::deserialize(&sub_read).unwrap();
+pub fn remove_substance(name: Option<String>, yes: bool) -> Result<(), std::io::Error> {
+    let mut store = FileStore::substances();
+    let sub_dec = ensure_substance_file();
 
-    let substances = substances_to_vec();
-    let substances_select =
-        inquire::MultiSelect::new("Which substance do you want to remove?", substances)
-            .prompt()
-            .unwrap();
-    dbg!(&substances_select);
+    let substances_select = match name {
+        Some(name) => {
+            if find_substance_uuid(&sub_dec, &name).is_none() {
+                eprintln!("Substance '{}' does not exist!", name);
+                exit(1);
+            }
+            vec![name]
+        }
+        None => {
+            ensure_interactive("the substance name");
+            let substances = substances_to_vec();
+            inquire::MultiSelect::new("Which substance do you want to remove?", substances)
+                .prompt()
+                .unwrap()
+        }
+    };
     for name in substances_select {
-        let confirm = inquire::prompt_confirmation(format!(
-            "Are you sure you want to remove '{}'? [y/N]",
-            name
-        ))
-        .unwrap();
+        let confirm = yes || {
+            ensure_interactive("--yes");
+            inquire::prompt_confirmation(format!(
+                "Are you sure you want to remove '{}'? [y/N]",
+                name
+            ))
+            .unwrap()
+        };
         if confirm {
-            // Clone to avoid immutable borrow
-            let sub_dec_clone = sub_dec.clone();
-            let uuid =
-                sub_dec_clone
-                    .iter()
-                    .find_map(|(id, val)| if val.name == name { Some(id) } else { None });
-            if uuid.is_some() {
-                let _ = sub_dec
-                    .remove(uuid.expect("Fatal error. Couldn't find substance UUID in HashMap."));
+            if let Some(uuid) = find_substance_uuid(&sub_dec, &name) {
+                store.delete(&uuid)?;
             }
         }
     }
-
-    let sub_enc = bincode::serialize(&sub_dec).unwrap();
-    match std::fs::write(SUBSTANCES_FILE.to_string(), sub_enc) {
-        Ok(_) => Ok(()),
-        Err(e) => Err(e),
-    }
+    Ok(())
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, strum::Display, strum::EnumIter)]
 pub enum SubstanceEditOptions {
     Name,
     Class,
+    Concentration,
+    Units,
 }
 
-pub fn edit_substance() -> Result<(), std::io::Error> {
-    let sub_read = std::fs::read(SUBSTANCES_FILE.to_string()).unwrap();
-    let mut sub_dec: HashMap<Uuid, Substance> = bincode::deserialize(&sub_read).unwrap();
+pub fn edit_substance(
+    name: Option<String>,
+    rename: Option<String>,
+    substance_class: Option<SubstanceClass>,
+    concentration: Option<Concentration>,
+    units: Vec<CustomUnit>,
+    remove_units: Vec<String>,
+) -> Result<(), std::io::Error> {
+    let mut store = FileStore::substances();
+    let sub_dec = ensure_substance_file();
 
-    let substances = substances_to_vec();
-    let substance_name = inquire::Select::new("Which substance do you want to edit?", substances)
-        .prompt()
-        .unwrap();
-    dbg!(&substance_name);
-    let sub_dec_clone = sub_dec.clone();
-    let uuid_opt = sub_dec_clone.iter().find_map(|(id, val)| {
-        if val.name == substance_name {
-            Some(id)
-        } else {
-            None
+    let substance_name = match name {
+        Some(name) => name,
+        None => {
+            ensure_interactive("the substance name");
+            let substances = substances_to_vec();
+            inquire::Select::new("Which substance do you want to edit?", substances)
+                .prompt()
+                .unwrap()
+        }
+    };
+    let uuid = match find_substance_uuid(&sub_dec, &substance_name) {
+        Some(uuid) => uuid,
+        None => {
+            eprintln!("Substance '{}' does not exist!", substance_name);
+            exit(1);
+        }
+    };
+    let mut substance = store
+        .get(&uuid)?
+        .expect("Fatal error. Couldn't find substance UUID in the store.");
+
+    if rename.is_some()
+        || substance_class.is_some()
+        || concentration.is_some()
+        || !units.is_empty()
+        || !remove_units.is_empty()
+    {
+        if let Some(name_updated) = rename {
+            substance.name = name_updated;
         }
-    });
-    if uuid_opt.is_some() {
-        let uuid = uuid_opt.clone().unwrap().to_owned();
-        let _ = sub_dec
-            .remove(uuid_opt.expect("Fatal error. Couldn't find substance UUID in HashMap."));
+        if let Some(substance_class) = substance_class {
+            substance.substance_class = substance_class;
+        }
+        if concentration.is_some() {
+            substance.concentration = concentration;
+        }
+        for name in remove_units {
+            if substance.find_unit(&name).is_none() {
+                eprintln!("Substance '{}' has no unit '{}'!", substance_name, name);
+                exit(1);
+            }
+            substance.units.retain(|unit| !unit.matches(&name));
+        }
+        for unit in units {
+            set_unit(&mut substance, unit);
+        }
+    } else {
+        ensure_interactive("--rename, --class, --concentration or --unit");
         let edit_select = inquire::Select::new(
             format!("[{}] What do you want to edit?", substance_name).as_str(),
             SubstanceEditOptions::iter().collect::<Vec<_>>(),
@@ -138,25 +256,11 @@ pub fn edit_substance() -> Result<(), std::io::Error> {
         .unwrap();
         match edit_select {
             SubstanceEditOptions::Name => {
-                let name_updated = inquire::prompt_text("What should the new name be?").unwrap();
-                let class = match sub_dec_clone
-                    .get(uuid_opt.expect("Fatal error. Couldn't find substance UUID in HashMap."))
-                {
-                    Some(class) => class.substance_class,
-                    None => {
-                        panic!("Fatal error. Couldn't find substance UUID in HashMap.")
-                    }
-                };
-                dbg!(&class);
-                let substance = Substance {
-                    name: name_updated,
-                    substance_class: class,
-                };
-                sub_dec.insert(uuid, substance);
+                substance.name = inquire::prompt_text("What should the new name be?").unwrap();
             }
             SubstanceEditOptions::Class => {
                 let class_variants = SubstanceClass::iter().collect::<Vec<_>>();
-                let substance_class = get_substance_class(
+                substance.substance_class = get_substance_class(
                     format!(
                         "[{}] What should the new substance class be?",
                         substance_name
@@ -164,15 +268,46 @@ pub fn edit_substance() -> Result<(), std::io::Error> {
                     .as_str(),
                     class_variants,
                 );
-                let substance = Substance {
-                    name: substance_name,
-                    substance_class,
-                };
-                sub_dec.insert(uuid, substance);
+            }
+            SubstanceEditOptions::Concentration => {
+                substance.concentration = inquire::CustomType::<Concentration>::new(
+                    format!("[{}] How much substance is in one ml?", substance_name).as_str(),
+                )
+                .with_placeholder("1.12g/ml")
+                .with_error_message("Please enter a concentration like 1.12g/ml or 100ug/ml")
+                .prompt_skippable()
+                .unwrap();
+            }
+            SubstanceEditOptions::Units => {
+                let unit = inquire::CustomType::<CustomUnit>::new(
+                    format!("[{}] Define a unit", substance_name).as_str(),
+                )
+                .with_placeholder("tab=100ug")
+                .with_help_message("An existing unit with the same name is replaced")
+                .with_error_message("Please enter a unit like tab=100ug or drink=14g")
+                .prompt()
+                .unwrap();
+                set_unit(&mut substance, unit);
             }
         }
-        let sub_enc = bincode::serialize(&sub_dec).unwrap();
-        std::fs::write(SUBSTANCES_FILE.to_string(), sub_enc).unwrap();
     }
-    Ok(())
+
+    if substance.name != substance_name && find_substance_uuid(&sub_dec, &substance.name).is_some()
+    {
+        eprintln!("Substance '{}' already exists!", substance.name);
+        exit(1);
+    }
+    store.update(uuid, substance)
+}
+
+/// Adds `unit` to the substance, replacing a unit with the same name.
+fn set_unit(substance: &mut Substance, unit: CustomUnit) {
+    substance.units.retain(|u| !u.matches(&unit.name));
+    substance.units.push(unit);
+}
+
+fn find_substance_uuid(substances: &HashMap<Uuid, Substance>, name: &str) -> Option<Uuid> {
+    substances
+        .iter()
+        .find_map(|(id, val)| if val.name == name { Some(*id) } else { None })
 }
@@ -0,0 +1,311 @@
+use chrono::{Duration, NaiveDateTime, Timelike, Utc};
+
+use crate::drugs_parser::{TimeRange, DRUGS};
+use crate::ingestions::Ingestion;
+use crate::ingestions_util::{
+    ensure_ingestion_files, format_dose, ingestion_datetime, DEFAULT_ACTIVE_HOURS,
+};
+use crate::util::format_span;
+
+/// Expected phases of an ingestion according to the drug database.
+pub struct Phases {
+    pub taken: NaiveDateTime,
+    pub onset: Option<(NaiveDateTime, NaiveDateTime)>,
+    /// Estimated, the database has no peak timings
+    pub peak: Option<(NaiveDateTime, NaiveDateTime)>,
+    pub end: Option<(NaiveDateTime, NaiveDateTime)>,
+    pub after_effects_end: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    Onset,
+    Peak,
+    Comedown,
+    AfterEffects,
+}
+
+impl Phase {
+    fn symbol(&self, ascii: bool) -> char {
+        match (self, ascii) {
+            (Phase::Onset, false) => '▒',
+            (Phase::Peak, false) => '█',
+            (Phase::Comedown, false) => '▓',
+            (Phase::AfterEffects, false) => '░',
+            (Phase::Onset, true) => '-',
+            (Phase::Peak, true) => '#',
+            (Phase::Comedown, true) => '=',
+            (Phase::AfterEffects, true) => '.',
+        }
+    }
+}
+
+impl Phases {
+    /// Phase at `time`, phases without data are skipped.
+    pub fn phase_at(&self, time: NaiveDateTime) -> Option<Phase> {
+        let onset_end = self.onset.map_or(self.taken, |(_, end)| end);
+        let peak_end = self.peak.map_or(onset_end, |(_, end)| end);
+        let end = self.end.map_or(peak_end, |(_, end)| end);
+        let after_effects_end = self.after_effects_end.unwrap_or(end);
+        if time < self.taken {
+            None
+        } else if time < onset_end {
+            Some(Phase::Onset)
+        } else if time < peak_end {
+            Some(Phase::Peak)
+        } else if time < end {
+            Some(Phase::Comedown)
+        } else if time < after_effects_end {
+            Some(Phase::AfterEffects)
+        } else {
+            None
+        }
+    }
+
+    /// Last moment anything is expected to be felt.
+    pub fn until(&self) -> Option<NaiveDateTime> {
+        self.after_effects_end
+            .or(self.end.map(|(_, end)| end))
+            .or(self.onset.map(|(_, end)| end))
+    }
+}
+
+fn after(time: NaiveDateTime, hours: f64) -> NaiveDateTime {
+    time + Duration::minutes((hours * 60.0).round() as i64)
+}
+
+/// Works out the phases for the ingestion's route, `None` without any timing data.
+///
+/// The peak is estimated to last from the end of the onset until half of the shortest expected
+/// duration has passed.
+pub fn phases(ingestion: &Ingestion) -> Option<Phases> {
+    let drug = DRUGS.get(&ingestion.substance.name)?;
+    let routes = ingestion.ingestion_method.routes();
+    let taken = ingestion_datetime(ingestion);
+    let timing = |duration: &Option<crate::drugs_parser::Duration>| -> Option<TimeRange> {
+        duration.as_ref()?.timing(&routes)
+    };
+    let onset = timing(&drug.formatted_onset);
+    let duration = timing(&drug.formatted_duration);
+    let after_effects = timing(&drug.formatted_aftereffects);
+    if onset.is_none() && duration.is_none() && after_effects.is_none() {
+        return None;
+    }
+
+    let peak = duration.map(|duration| {
+        let start = onset.map_or(0.0, |onset| onset.max);
+        (
+            after(taken, start),
+            after(taken, start.max(duration.min / 2.0)),
+        )
+    });
+    Some(Phases {
+        taken,
+        onset: onset.map(|onset| (after(taken, onset.min), after(taken, onset.max))),
+        peak,
+        end: duration.map(|duration| (after(taken, duration.min), after(taken, duration.max))),
+        after_effects_end: after_effects
+            .map(|after_effects| after(taken, duration.map_or(0.0, |d| d.max) + after_effects.max)),
+    })
+}
+
+/// Shows what is still in effect from recent ingestions.
+pub fn active() -> Result<(), std::io::Error> {
+    let now = Utc::now().naive_utc();
+    let ingestions = ensure_ingestion_files();
+    let mut active = ingestions
+        .values()
+        .map(|ingestion| (ingestion, phases(ingestion)))
+        .filter(|(ingestion, phases)| {
+            let taken = ingestion_datetime(ingestion);
+            let until = phases
+                .as_ref()
+                .and_then(Phases::until)
+                .unwrap_or_else(|| after(taken, DEFAULT_ACTIVE_HOURS));
+            taken <= now && now < until
+        })
+        .collect::<Vec<_>>();
+    if active.is_empty() {
+        println!("No active ingestions.");
+        return Ok(());
+    }
+    active.sort_by_key(|(ingestion, _)| ingestion_datetime(ingestion));
+
+    for (ingestion, phases) in active {
+        let taken = ingestion_datetime(ingestion);
+        println!(
+            "{} {} ({}), taken {}, {} ago",
+            ingestion.substance.name,
+            format_dose(ingestion),
+            ingestion.ingestion_method,
+            format_time(taken, now),
+            format_span(now - taken)
+        );
+        let phases = match phases {
+            Some(phases) => phases,
+            None => {
+                println!(
+                    "  No timing data, counted as active for {} hours\n",
+                    DEFAULT_ACTIVE_HOURS
+                );
+                continue;
+            }
+        };
+        let rows = [
+            ("Onset:", phases.onset),
+            ("Peak (est.):", phases.peak),
+            ("End:", phases.end),
+        ];
+        for (label, window) in rows {
+            if let Some((start, end)) = window {
+                let times = if start == end {
+                    format_time(start, now)
+                } else {
+                    format!("{} - {}", format_time(start, now), format_time(end, now))
+                };
+                println!("  {:<15}{:<28}{}", label, times, relative(start, end, now));
+            }
+        }
+        if let Some(end) = phases.after_effects_end {
+            let times = format!("until {}", format_time(end, now));
+            println!(
+                "  {:<15}{:<28}{}",
+                "After-effects:",
+                times,
+                relative(end, end, now)
+            );
+        }
+        println!();
+    }
+    Ok(())
+}
+
+/// Total width of the chart including the labels.
+const WIDTH: usize = 80;
+const LABEL_WIDTH: usize = 24;
+
+/// Draws one row per ingestion of the last `hours` hours, with a quarter of that ahead of now
+/// to show what is still to come.
+pub fn chart(hours: i64, ascii: bool) -> Result<(), std::io::Error> {
+    let now = Utc::now().naive_utc();
+    let start = now - Duration::hours(hours);
+    let end = now + Duration::minutes(hours * 15);
+    let columns = WIDTH - LABEL_WIDTH;
+    let column_time = |column: usize| {
+        let span = (end - start).num_seconds() as f64;
+        start + Duration::seconds((span * (column as f64 + 0.5) / columns as f64) as i64)
+    };
+    let now_column = ((now - start).num_seconds() as f64 / (end - start).num_seconds() as f64
+        * columns as f64) as usize;
+
+    let ingestions = ensure_ingestion_files();
+    let mut rows = ingestions
+        .values()
+        .filter(|ingestion| {
+            let taken = ingestion_datetime(ingestion);
+            start <= taken && taken <= now
+        })
+        .collect::<Vec<_>>();
+    if rows.is_empty() {
+        println!("No ingestions in the last {} hours.", hours);
+        return Ok(());
+    }
+    rows.sort_by(|a, b| {
+        (ingestion_datetime(a), &a.substance.name).cmp(&(ingestion_datetime(b), &b.substance.name))
+    });
+
+    let now_marker = if ascii { '|' } else { '│' };
+    for ingestion in rows {
+        let taken = ingestion_datetime(ingestion);
+        let phases = phases(ingestion);
+        let label = format!(
+            "{} {}",
+            taken.format("%H:%M"),
+            ingestion
+                .substance
+                .name
+                .chars()
+                .take(LABEL_WIDTH - 7)
+                .collect::<String>()
+        );
+        let bar = (0..columns)
+            .map(|column| {
+                let time = column_time(column);
+                let phase = phases.as_ref().and_then(|phases| phases.phase_at(time));
+                match phase {
+                    Some(phase) => phase.symbol(ascii),
+                    // Substances without timings only get a mark where they were taken
+                    None if phases.is_none()
+                        && column_time(column + 1) > taken
+                        && time <= taken =>
+                    {
+                        '?'
+                    }
+                    None if column == now_column => now_marker,
+                    None => ' ',
+                }
+            })
+            .collect::<String>();
+        println!("{:<width$}{}", label, bar, width = LABEL_WIDTH);
+    }
+
+    // Axis with a label every few hours, aligned to full hours
+    let step = (hours / 4).max(1);
+    let mut axis = vec![' '; columns + 6];
+    let mut hour = start.date().and_hms_opt(start.hour(), 0, 0).unwrap() + Duration::hours(1);
+    while hour < end {
+        if hour.hour() as i64 % step == 0 {
+            let column = ((hour - start).num_seconds() as f64 / (end - start).num_seconds() as f64
+                * columns as f64) as usize;
+            for (i, c) in hour.format("%H:%M").to_string().chars().enumerate() {
+                if let Some(cell) = axis.get_mut(column + i) {
+                    *cell = c;
+                }
+            }
+        }
+        hour += Duration::hours(1);
+    }
+    println!(
+        "{:<width$}{}",
+        "",
+        axis.into_iter().collect::<String>().trim_end(),
+        width = LABEL_WIDTH
+    );
+    println!(
+        "\n{} onset  {} peak (est.)  {} comedown  {} after-effects  {} now  ? no timing data",
+        Phase::Onset.symbol(ascii),
+        Phase::Peak.symbol(ascii),
+        Phase::Comedown.symbol(ascii),
+        Phase::AfterEffects.symbol(ascii),
+        now_marker
+    );
+    Ok(())
+}
+
+/// Time of day for today, the full date otherwise.
+fn format_time(time: NaiveDateTime, now: NaiveDateTime) -> String {
+    if time.date() == now.date() {
+        time.format("%H:%M").to_string()
+    } else {
+        time.format("%Y-%m-%d %H:%M").to_string()
+    }
+}
+
+/// Where `now` is relative to the window from `start` to `end`.
+fn relative(start: NaiveDateTime, end: NaiveDateTime, now: NaiveDateTime) -> String {
+    if now < start {
+        if start == end {
+            format!("in {}", format_span(start - now))
+        } else {
+            format!(
+                "in {} - {}",
+                format_span(start - now),
+                format_span(end - now)
+            )
+        }
+    } else if now <= end {
+        "now".to_string()
+    } else {
+        "done".to_string()
+    }
+}
@@ -0,0 +1,324 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::drugs_parser::{parse_tolerance_days, Category, ComboGroup, Drug, DRUGS};
+use crate::ingestions::Ingestion;
+use crate::ingestions_util::{ensure_ingestion_files, ingestion_datetime, is_same_substance};
+use crate::substances::{Substance, SubstanceClass};
+
+/// Substances that share tolerance with each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display)]
+pub enum ToleranceGroup {
+    Psychedelics,
+    Empathogens,
+    Stimulants,
+    Opioids,
+    #[strum(to_string = "GABAergic depressants")]
+    Gabaergics,
+    Dissociatives,
+    Cannabinoids,
+}
+
+impl ToleranceGroup {
+    /// Rough number of days until tolerance from a single dose is gone.
+    pub fn baseline_days(&self) -> f64 {
+        match self {
+            ToleranceGroup::Psychedelics => 14.0,
+            ToleranceGroup::Empathogens => 30.0,
+            ToleranceGroup::Stimulants => 7.0,
+            ToleranceGroup::Opioids => 14.0,
+            ToleranceGroup::Gabaergics => 14.0,
+            ToleranceGroup::Dissociatives => 14.0,
+            ToleranceGroup::Cannabinoids => 14.0,
+        }
+    }
+
+    /// Groups a logged substance belongs to, from the drug database or its class otherwise.
+    pub fn of(substance: &Substance) -> Vec<ToleranceGroup> {
+        match DRUGS.get(&substance.name) {
+            Some(drug) => ToleranceGroup::of_drug(drug),
+            None => ToleranceGroup::of_class(substance.substance_class),
+        }
+    }
+
+    pub fn of_drug(drug: &Drug) -> Vec<ToleranceGroup> {
+        let combo_groups = drug.combo_groups();
+        let in_group = |groups: &[ComboGroup]| groups.iter().any(|g| combo_groups.contains(g));
+        let class = drug.substance_class();
+        let categories = drug.categories.as_deref().unwrap_or_default();
+
+        let mut groups = Vec::new();
+        // DMT is known for barely building tolerance
+        if class == Some(SubstanceClass::Psychedelic) && !in_group(&[ComboGroup::Dmt]) {
+            groups.push(ToleranceGroup::Psychedelics);
+        }
+        if class == Some(SubstanceClass::Empathogen) || in_group(&[ComboGroup::Mdma]) {
+            groups.push(ToleranceGroup::Empathogens);
+        }
+        if (class == Some(SubstanceClass::Stimulant)
+            || in_group(&[
+                ComboGroup::Amphetamines,
+                ComboGroup::Cocaine,
+                ComboGroup::Mephedrone,
+            ]))
+            && !in_group(&[ComboGroup::Caffeine])
+        {
+            groups.push(ToleranceGroup::Stimulants);
+        }
+        if in_group(&[ComboGroup::Opioids, ComboGroup::Tramadol]) {
+            groups.push(ToleranceGroup::Opioids);
+        }
+        if in_group(&[
+            ComboGroup::Benzodiazepines,
+            ComboGroup::Alcohol,
+            ComboGroup::GhbGbl,
+        ]) || categories.contains(&Category::Barbiturate)
+        {
+            groups.push(ToleranceGroup::Gabaergics);
+        }
+        if class == Some(SubstanceClass::Dissociative)
+            || in_group(&[
+                ComboGroup::Ketamine,
+                ComboGroup::Mxe,
+                ComboGroup::Pcp,
+                ComboGroup::Dextromethorphan,
+            ])
+        {
+            groups.push(ToleranceGroup::Dissociatives);
+        }
+        if class == Some(SubstanceClass::Cannabinoid) || in_group(&[ComboGroup::Cannabis]) {
+            groups.push(ToleranceGroup::Cannabinoids);
+        }
+        groups
+    }
+
+    fn of_class(class: SubstanceClass) -> Vec<ToleranceGroup> {
+        match class {
+            SubstanceClass::Psychedelic => vec![ToleranceGroup::Psychedelics],
+            SubstanceClass::Empathogen => vec![ToleranceGroup::Empathogens],
+            SubstanceClass::Stimulant => vec![ToleranceGroup::Stimulants],
+            SubstanceClass::Dissociative => vec![ToleranceGroup::Dissociatives],
+            SubstanceClass::Cannabinoid => vec![ToleranceGroup::Cannabinoids],
+            _ => vec![],
+        }
+    }
+}
+
+/// Days until baseline for substances outside of any group.
+const DEFAULT_BASELINE_DAYS: f64 = 7.0;
+
+/// Estimated tolerance after a series of doses.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tolerance {
+    pub last_dose: NaiveDateTime,
+    pub baseline: NaiveDateTime,
+    /// Rough share of the tolerance of a single fresh dose left, `1.0` or more right after one
+    pub level: f64,
+}
+
+impl Tolerance {
+    /// Estimates tolerance from the times of doses.
+    ///
+    /// Every dose adds `baseline_days` of tolerance which wears off one day per day, so repeated
+    /// use pushes the return to baseline further out. It is capped at three single doses worth.
+    pub fn estimate(
+        doses: &[NaiveDateTime],
+        baseline_days: f64,
+        now: NaiveDateTime,
+    ) -> Option<Tolerance> {
+        let mut doses = doses
+            .iter()
+            .filter(|&&dose| dose <= now)
+            .collect::<Vec<_>>();
+        doses.sort();
+        let days = |span: Duration| span.num_minutes() as f64 / (24.0 * 60.0);
+
+        let mut left = 0.0_f64;
+        let mut previous: Option<NaiveDateTime> = None;
+        for &&dose in &doses {
+            if let Some(previous) = previous {
+                left = (left - days(dose - previous)).max(0.0);
+            }
+            left = (left + baseline_days).min(baseline_days * 3.0);
+            previous = Some(dose);
+        }
+        let last_dose = previous?;
+        let baseline = last_dose + Duration::minutes((left * 24.0 * 60.0) as i64);
+        let remaining = (left - days(now - last_dose)).max(0.0);
+        Some(Tolerance {
+            last_dose,
+            baseline,
+            level: remaining / baseline_days,
+        })
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match self.level {
+            level if level >= 0.66 => "high",
+            level if level >= 0.33 => "moderate",
+            level if level > 0.0 => "low",
+            _ => "back to baseline",
+        }
+    }
+}
+
+/// Days until baseline after a single dose of `name`, from the drug database's tolerance notes
+/// or the first group it belongs to.
+fn baseline_days(name: &str, groups: &[ToleranceGroup]) -> f64 {
+    DRUGS
+        .get(name)
+        .and_then(|drug| parse_tolerance_days(drug.properties.tolerance.as_ref()?))
+        .or_else(|| groups.first().map(|group| group.baseline_days()))
+        .unwrap_or(DEFAULT_BASELINE_DAYS)
+}
+
+/// Estimated tolerance for `name` itself and every group in `groups`, with the name of the
+/// substance that was taken last for groups.
+fn tolerances(
+    name: &str,
+    groups: &[ToleranceGroup],
+    ingestions: &HashMap<Uuid, Ingestion>,
+    now: NaiveDateTime,
+) -> Vec<(String, Tolerance, Option<String>)> {
+    let mut estimates = Vec::new();
+
+    let own = ingestions
+        .values()
+        .filter(|i| is_same_substance(&i.substance.name, name))
+        .map(ingestion_datetime)
+        .collect::<Vec<_>>();
+    if let Some(tolerance) = Tolerance::estimate(&own, baseline_days(name, groups), now) {
+        estimates.push((name.to_string(), tolerance, None));
+    }
+
+    for group in groups {
+        let members = ingestions
+            .values()
+            .filter(|i| ToleranceGroup::of(&i.substance).contains(group))
+            .collect::<Vec<_>>();
+        let times = members
+            .iter()
+            .map(|i| ingestion_datetime(i))
+            .collect::<Vec<_>>();
+        if let Some(tolerance) = Tolerance::estimate(&times, group.baseline_days(), now) {
+            let latest = members
+                .iter()
+                .find(|i| ingestion_datetime(i) == tolerance.last_dose)
+                .map(|i| i.substance.name.clone());
+            estimates.push((group.to_string(), tolerance, latest));
+        }
+    }
+    estimates
+}
+
+fn format_estimate(
+    name: &str,
+    tolerance: &Tolerance,
+    latest: Option<&String>,
+    now: NaiveDateTime,
+) -> String {
+    let days = (now - tolerance.last_dose).num_days();
+    let since = match days {
+        0 => "last dose today".to_string(),
+        1 => "last dose 1 day ago".to_string(),
+        days => format!("last dose {} days ago", days),
+    };
+    let since = match latest {
+        Some(latest) => format!("{} ({})", since, latest),
+        None => since,
+    };
+    if tolerance.level > 0.0 {
+        format!(
+            "{}: {}, {}, baseline around {}",
+            name,
+            since,
+            tolerance.describe(),
+            tolerance.baseline.format("%Y-%m-%d")
+        )
+    } else {
+        format!("{}: {}, {}", name, since, tolerance.describe())
+    }
+}
+
+/// Tolerance section of `substance-info`, nothing is shown without logged ingestions.
+pub fn print_tolerance(drug: &Drug) {
+    let now = Utc::now().naive_utc();
+    let ingestions = ensure_ingestion_files();
+    let groups = ToleranceGroup::of_drug(drug);
+    let estimates = tolerances(&drug.pretty_name, &groups, &ingestions, now);
+    if estimates.is_empty() {
+        return;
+    }
+    println!("\nYour tolerance (rough estimate)");
+    println!("-------------------------------");
+    for (name, tolerance, latest) in &estimates {
+        println!(
+            "  {}",
+            format_estimate(name, tolerance, latest.as_ref(), now)
+        );
+    }
+}
+
+/// Warns before logging `ingestion` if earlier ingestions likely left some tolerance.
+pub fn warn_tolerance(ingestion: &Ingestion, ingestions: &HashMap<Uuid, Ingestion>) {
+    let taken = ingestion_datetime(ingestion);
+    let groups = ToleranceGroup::of(&ingestion.substance);
+    let estimates = tolerances(&ingestion.substance.name, &groups, ingestions, taken);
+    let tolerant = estimates
+        .iter()
+        .filter(|(_, tolerance, _)| tolerance.level > 0.0)
+        .collect::<Vec<_>>();
+    if tolerant.is_empty() {
+        return;
+    }
+    println!("Tolerance (rough estimate):");
+    for (name, tolerance, latest) in tolerant {
+        println!(
+            "  {}",
+            format_estimate(name, tolerance, latest.as_ref(), taken)
+        );
+    }
+    println!("  The same dose may feel weaker, don't redose to make up for it.");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Tolerance;
+    use chrono::{Duration, NaiveDate, NaiveDateTime};
+
+    fn day(day: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn estimates_single_doses() {
+        let tolerance = Tolerance::estimate(&[day(1)], 14.0, day(8)).unwrap();
+        assert_eq!(tolerance.baseline, day(15));
+        assert_eq!(tolerance.level, 0.5);
+        assert_eq!(tolerance.describe(), "moderate");
+
+        let tolerance = Tolerance::estimate(&[day(1)], 14.0, day(20)).unwrap();
+        assert_eq!(tolerance.level, 0.0);
+        assert_eq!(tolerance.describe(), "back to baseline");
+
+        assert_eq!(Tolerance::estimate(&[], 14.0, day(1)), None);
+        assert_eq!(Tolerance::estimate(&[day(5)], 14.0, day(1)), None);
+    }
+
+    #[test]
+    fn repeated_doses_push_back_baseline() {
+        // Second dose while still tolerant adds on top of what is left
+        let tolerance = Tolerance::estimate(&[day(1), day(3)], 7.0, day(3)).unwrap();
+        assert_eq!(tolerance.baseline, day(3) + Duration::days(12));
+        assert!(tolerance.level > 1.0);
+
+        // Daily use is capped at three doses worth
+        let daily = (1..=20).map(day).collect::<Vec<_>>();
+        let tolerance = Tolerance::estimate(&daily, 7.0, day(20)).unwrap();
+        assert_eq!(tolerance.baseline, day(20) + Duration::days(21));
+    }
+}
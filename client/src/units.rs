@@ -0,0 +1,303 @@
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::drugs_parser::RangeUnit;
+use crate::ingestions::Dose;
+use crate::substances::Substance;
+use strum::IntoEnumIterator;
+
+/// Unit of a logged dose.
+#[derive(
+    Serialize,
+    Deserialize,
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    strum::Display,
+    strum::EnumIter,
+    strum::EnumString,
+)]
+#[strum(serialize_all = "snake_case", ascii_case_insensitive)]
+pub enum DoseUnit {
+    #[strum(to_string = "ug", serialize = "µg", serialize = "mcg")]
+    Ug,
+    Mg,
+    G,
+    Ml,
+    /// Unit defined per substance like `tab` or `drink`, new variants go before this one
+    #[strum(default)]
+    Custom(String),
+}
+
+/// What a unit measures, conversions between the two need a concentration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dimension {
+    Mass,
+    Volume,
+    Count,
+}
+
+impl DoseUnit {
+    pub fn dimension(&self) -> Dimension {
+        match self {
+            DoseUnit::Ug | DoseUnit::Mg | DoseUnit::G => Dimension::Mass,
+            DoseUnit::Ml => Dimension::Volume,
+            DoseUnit::Custom(_) => Dimension::Count,
+        }
+    }
+
+    /// Size of the unit in milligrams for masses and millilitres for volumes.
+    fn base(&self) -> f64 {
+        match self {
+            DoseUnit::Ug => 0.001,
+            DoseUnit::Mg => 1.0,
+            DoseUnit::G => 1000.0,
+            DoseUnit::Ml | DoseUnit::Custom(_) => 1.0,
+        }
+    }
+
+    /// Units that work for every substance.
+    pub fn builtin() -> Vec<DoseUnit> {
+        DoseUnit::iter()
+            .filter(|unit| unit.dimension() != Dimension::Count)
+            .collect()
+    }
+
+    pub fn from_range_unit(unit: RangeUnit) -> Option<DoseUnit> {
+        match unit {
+            RangeUnit::Ug => Some(DoseUnit::Ug),
+            RangeUnit::Mg => Some(DoseUnit::Mg),
+            RangeUnit::G => Some(DoseUnit::G),
+            RangeUnit::Ml => Some(DoseUnit::Ml),
+            _ => None,
+        }
+    }
+}
+
+/// Mass of substance per millilitre of a liquid, e.g. `1.12g/ml` for GBL or `100ug/ml` for an
+/// LSD solution.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Concentration {
+    pub milligrams_per_ml: f64,
+}
+
+impl FromStr for Concentration {
+    type Err = String;
+
+    /// Parses concentrations like `1.12g/ml`, `100 ug/ml` or a bare number of mg per ml.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let mass = s
+            .strip_suffix("/ml")
+            .or_else(|| s.strip_suffix("/mL"))
+            .unwrap_or(s)
+            .trim();
+        let split = mass
+            .find(|c: char| c.is_alphabetic() || c == 'µ')
+            .unwrap_or(mass.len());
+        let (value, unit) = mass.split_at(split);
+        let value: f64 = value
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid concentration `{}`", s))?;
+        let unit = match unit.trim() {
+            "" => DoseUnit::Mg,
+            unit => DoseUnit::from_str(unit)
+                .ok()
+                .filter(|unit| unit.dimension() == Dimension::Mass)
+                .ok_or_else(|| format!("Unknown mass unit `{}` in concentration", unit))?,
+        };
+        if value <= 0.0 {
+            return Err(format!("Concentration `{}` must be positive", s));
+        }
+        Ok(Concentration {
+            milligrams_per_ml: value * unit.base(),
+        })
+    }
+}
+
+impl std::fmt::Display for Concentration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.milligrams_per_ml >= 1000.0 {
+            write!(f, "{}g/ml", self.milligrams_per_ml / 1000.0)
+        } else if self.milligrams_per_ml < 1.0 {
+            write!(f, "{}ug/ml", self.milligrams_per_ml * 1000.0)
+        } else {
+            write!(f, "{}mg/ml", self.milligrams_per_ml)
+        }
+    }
+}
+
+/// A substance specific unit like `tab = 100ug` or `drink = 14g`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, PartialOrd)]
+pub struct CustomUnit {
+    pub name: String,
+    pub amount: f64,
+    /// Always a built-in unit
+    pub unit: DoseUnit,
+}
+
+impl CustomUnit {
+    /// Matches the unit name case-insensitively and in plural, so `2 tabs` works for `tab`.
+    pub fn matches(&self, name: &str) -> bool {
+        let name = name.trim();
+        self.name.eq_ignore_ascii_case(name)
+            || name
+                .strip_suffix('s')
+                .is_some_and(|name| self.name.eq_ignore_ascii_case(name))
+    }
+}
+
+impl FromStr for CustomUnit {
+    type Err = String;
+
+    /// Parses definitions like `tab=100ug`, `1 drink = 14g` or `drop = 0.05ml`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, dose) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Unit `{}` should look like `tab=100ug`", s.trim()))?;
+        let name = name
+            .trim()
+            .trim_start_matches(|c: char| c.is_ascii_digit() || c == ' ');
+        if name.is_empty() {
+            return Err(format!("Unit `{}` is missing a name", s.trim()));
+        }
+        if !matches!(DoseUnit::from_str(name), Ok(DoseUnit::Custom(_))) {
+            return Err(format!("`{}` is already a built-in unit", name));
+        }
+        let dose = Dose::from_str(dose)?;
+        if dose.unit.dimension() == Dimension::Count {
+            return Err(format!(
+                "Unit `{}` has to be defined as a mass or volume, not `{}`",
+                name, dose.unit
+            ));
+        }
+        Ok(CustomUnit {
+            name: name.to_string(),
+            amount: dose.value,
+            unit: dose.unit,
+        })
+    }
+}
+
+impl std::fmt::Display for CustomUnit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "1 {} = {}{}", self.name, self.amount, self.unit)
+    }
+}
+
+/// Converts `value` from one unit into another.
+///
+/// Masses convert freely, volumes only convert to and from masses when the substance has a
+/// concentration and custom units need to be defined for the substance.
+pub fn convert(value: f64, from: &DoseUnit, to: &DoseUnit, substance: &Substance) -> Option<f64> {
+    let (value, from) = resolve(value, from, substance)?;
+    let (per_unit, to) = resolve(1.0, to, substance)?;
+    let concentration = substance.concentration;
+    let base = value * from.base();
+    let base = match (from.dimension(), to.dimension()) {
+        (Dimension::Mass, Dimension::Mass) | (Dimension::Volume, Dimension::Volume) => base,
+        (Dimension::Volume, Dimension::Mass) => base * concentration?.milligrams_per_ml,
+        (Dimension::Mass, Dimension::Volume) => base / concentration?.milligrams_per_ml,
+        _ => return None,
+    };
+    Some(base / to.base() / per_unit)
+}
+
+/// Replaces a custom unit with the built-in unit it is defined in.
+fn resolve<'a>(
+    value: f64,
+    unit: &'a DoseUnit,
+    substance: &'a Substance,
+) -> Option<(f64, &'a DoseUnit)> {
+    match unit {
+        DoseUnit::Custom(name) => {
+            let custom = substance.find_unit(name)?;
+            Some((value * custom.amount, &custom.unit))
+        }
+        unit => Some((value, unit)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{convert, Concentration, CustomUnit, DoseUnit};
+    use crate::substances::{Substance, SubstanceClass};
+
+    fn substance(concentration: Option<&str>, units: &[&str]) -> Substance {
+        Substance {
+            name: "Test".to_string(),
+            substance_class: SubstanceClass::Depressant,
+            concentration: concentration.map(|c| c.parse().unwrap()),
+            units: units.iter().map(|u| u.parse().unwrap()).collect(),
+        }
+    }
+
+    #[test]
+    fn converts_between_mass_units() {
+        let plain = substance(None, &[]);
+        assert_eq!(
+            convert(1.5, &DoseUnit::G, &DoseUnit::Mg, &plain),
+            Some(1500.0)
+        );
+        assert_eq!(
+            convert(250.0, &DoseUnit::Ug, &DoseUnit::Mg, &plain),
+            Some(0.25)
+        );
+        assert_eq!(
+            convert(2.0, &DoseUnit::Ml, &DoseUnit::Ml, &plain),
+            Some(2.0)
+        );
+    }
+
+    #[test]
+    fn converts_volumes_with_a_concentration() {
+        let gbl: Concentration = "1.12g/ml".parse().unwrap();
+        assert_eq!(gbl.milligrams_per_ml, 1120.0);
+        let gbl = substance(Some("1.12g/ml"), &[]);
+        assert_eq!(convert(1.0, &DoseUnit::Ml, &DoseUnit::G, &gbl), Some(1.12));
+        assert_eq!(
+            convert(2240.0, &DoseUnit::Mg, &DoseUnit::Ml, &gbl),
+            Some(2.0)
+        );
+
+        let lsd: Concentration = "100 ug/ml".parse().unwrap();
+        assert_eq!(lsd.to_string(), "100ug/ml");
+        let lsd = substance(Some("100 ug/ml"), &[]);
+        assert_eq!(convert(0.5, &DoseUnit::Ml, &DoseUnit::Ug, &lsd), Some(50.0));
+
+        let plain = substance(None, &[]);
+        assert_eq!(convert(1.0, &DoseUnit::Ml, &DoseUnit::Mg, &plain), None);
+        assert!("5ml/ml".parse::<Concentration>().is_err());
+        assert!("0mg/ml".parse::<Concentration>().is_err());
+    }
+
+    #[test]
+    fn converts_custom_units() {
+        let tab = DoseUnit::Custom("tabs".to_string());
+        assert_eq!(tab.to_string(), "tabs");
+        assert_eq!(
+            "Tabs".parse::<DoseUnit>(),
+            Ok(DoseUnit::Custom("Tabs".to_string()))
+        );
+        let lsd = substance(None, &["tab=100ug"]);
+        assert_eq!(convert(2.0, &tab, &DoseUnit::Ug, &lsd), Some(200.0));
+        assert_eq!(convert(50.0, &DoseUnit::Ug, &tab, &lsd), Some(0.5));
+        assert_eq!(convert(1.0, &tab, &DoseUnit::Ml, &lsd), None);
+
+        let drop = DoseUnit::Custom("Drop".to_string());
+        let solution = substance(Some("1mg/ml"), &["drop = 0.05ml"]);
+        assert_eq!(convert(2.0, &drop, &DoseUnit::Mg, &solution), Some(0.1));
+        assert_eq!(convert(2.0, &tab, &DoseUnit::Ug, &solution), None);
+
+        let drink: CustomUnit = "1 standard drink = 14g".parse().unwrap();
+        assert_eq!(drink.name, "standard drink");
+        assert_eq!(drink.to_string(), "1 standard drink = 14g");
+        assert!(drink.matches("Standard Drinks"));
+        assert!("mg=1g".parse::<CustomUnit>().is_err());
+        assert!("tab=2caps".parse::<CustomUnit>().is_err());
+        assert!("tab".parse::<CustomUnit>().is_err());
+    }
+}
@@ -1,3 +1,46 @@
+use std::io::IsTerminal;
+
+/// Upper bound for `--hours` arguments, a century is far more than any log spans.
+pub const MAX_HOURS: i64 = 100 * 365 * 24;
+
 pub fn path_exists(path: String) -> bool {
     std::fs::metadata(path).is_ok()
 }
+
+/// Exits with an error instead of prompting for `missing` when stdin is not a terminal.
+pub fn ensure_interactive(missing: &str) {
+    if !std::io::stdin().is_terminal() {
+        eprintln!(
+            "Missing {} and stdin is not a terminal, refusing to prompt",
+            missing
+        );
+        std::process::exit(1);
+    }
+}
+
+/// Greedily wraps `text` at word boundaries, prefixing every line with `indent`.
+pub fn wrap_text(text: &str, width: usize, indent: &str) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = String::from(indent);
+    for word in text.split_whitespace() {
+        if line.len() > indent.len() && line.chars().count() + 1 + word.chars().count() > width {
+            lines.push(line);
+            line = String::from(indent);
+        }
+        if line.len() > indent.len() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    lines.push(line);
+    lines.join("\n")
+}
+
+/// Formats a time span like `2h 05m` or `45m`, negative spans are formatted like positive ones.
+pub fn format_span(span: chrono::Duration) -> String {
+    let minutes = span.num_minutes().abs();
+    match minutes / 60 {
+        0 => format!("{}m", minutes),
+        hours => format!("{}h {:02}m", hours, minutes % 60),
+    }
+}